@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fs, path::Path, process::Command};
+
+/// Record the git commit the tool is built from in KRUN_EIF_CTL_GIT_SHA, which is left empty
+/// when not building from a git checkout.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Only ask git when building from this crate's own checkout, so that a packaged copy nested
+    // in an unrelated repository does not record that repository's commit.
+    let git = Path::new(".git");
+    let mut sha = String::new();
+    if git.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Ok(head) = fs::read_to_string(git.join("HEAD"))
+            && let Some(reference) = head.trim().strip_prefix("ref: ")
+        {
+            println!("cargo:rerun-if-changed=.git/{reference}");
+        }
+
+        sha = Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_default();
+    }
+
+    println!("cargo:rustc-env=KRUN_EIF_CTL_GIT_SHA={sha}");
+}
@@ -1,8 +1,20 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use analyze::*;
 use anyhow::Result;
 use build::*;
 use clap::{Parser, Subcommand};
+use diff::*;
+use initrd::*;
+use inspect::*;
+use metadata::*;
+use schema::*;
+use std::{process, sync::mpsc, thread, time::Duration};
+use validate_modules::*;
+use verify::*;
+
+/// Exit code returned when a build exceeds its --timeout (matching timeout(1)).
+const TIMEOUT_EXIT_CODE: i32 = 124;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -13,46 +25,150 @@ struct Cmd {
 
 #[derive(Subcommand)]
 enum Subcmd {
-    Build(BuildArgs),
+    Build(Box<BuildArgs>),
+    Metadata(MetadataArgs),
+    ValidateModules(ValidateModulesArgs),
+    Analyze(AnalyzeArgs),
+    Inspect(InspectArgs),
+    Schema(SchemaArgs),
+    Verify(VerifyArgs),
+    Diff(DiffArgs),
+    Initrd(InitrdArgs),
 }
 
 fn main() -> Result<()> {
     let cmd = Cmd::parse();
 
     let status = match cmd.subcmd {
-        Subcmd::Build(args) => build::build(args),
+        Subcmd::Build(args) => match args.timeout {
+            Some(secs) => build_with_timeout(*args, Duration::from_secs(secs)),
+            None => build::build(*args),
+        },
+        Subcmd::Metadata(args) => metadata::metadata(args),
+        Subcmd::ValidateModules(args) => validate_modules::validate_modules(args),
+        Subcmd::Analyze(args) => analyze::analyze(args),
+        Subcmd::Inspect(args) => inspect::inspect(args),
+        Subcmd::Schema(args) => schema::schema(args),
+        Subcmd::Verify(args) => verify::verify(args),
+        Subcmd::Diff(args) => diff::diff(args),
+        Subcmd::Initrd(args) => initrd::initrd(args),
     };
 
     if let Err(ref e) = status {
-        eprintln!("ERROR: {e}");
-        e.chain()
-            .skip(1)
-            .for_each(|cause| eprintln!("\tcause: {cause}"));
+        print_error(e);
     }
 
     status
 }
 
+/// Run the build on a worker thread, aborting it (killing the processes it is waiting on and
+/// removing its partially-written outputs) if it does not complete within `timeout` or panics.
+fn build_with_timeout(args: BuildArgs, timeout: Duration) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _ = tx.send(build::build(args));
+    });
+
+    let abort = || {
+        build::kill_children();
+        for output in build::written_outputs() {
+            let _ = std::fs::remove_file(output);
+        }
+    };
+
+    match rx.recv_timeout(timeout) {
+        Ok(status) => status,
+        // The worker panicked (its panic message has already been printed).
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            abort();
+            Err(anyhow::anyhow!("build aborted unexpectedly"))
+        }
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            abort();
+
+            eprintln!(
+                "ERROR: build did not complete within {} second(s)",
+                timeout.as_secs()
+            );
+            process::exit(TIMEOUT_EXIT_CODE);
+        }
+    }
+}
+
+fn print_error(e: &anyhow::Error) {
+    eprintln!("ERROR: {e}");
+    e.chain()
+        .skip(1)
+        .for_each(|cause| eprintln!("\tcause: {cause}"));
+}
+
 /// Subcommand to build a new EIF image.
 pub mod build {
     use super::*;
-    use anyhow::Context;
+    use anyhow::{Context, anyhow};
     use aws_nitro_enclaves_image_format::{
         defs::{EIF_HDR_ARCH_ARM64, EifBuildInfo, EifIdentityInfo},
-        utils::EifBuilder,
+        utils::{EifBuilder, SignEnclaveInfo, get_pcrs},
     };
     use chrono::{DateTime, Utc};
-    use clap::ValueEnum;
+    use clap::{Args, ValueEnum};
     use cpio::{NewcBuilder, newc::trailer};
-    use serde_json::Value;
-    use sha2::{Digest, Sha384};
+    use flate2::read::GzDecoder;
+    use openssl::{
+        cms::{CMSOptions, CmsContentInfo},
+        stack::Stack,
+        x509::X509,
+    };
+    use regex::Regex;
+    use serde_json::{Map, Value, json};
+    use sha2::{Digest, Sha256, Sha384};
     use std::{
+        collections::BTreeMap,
+        env,
+        ffi::CString,
         fs::{self, File, OpenOptions},
-        io,
-        path::{Path, PathBuf},
-        time::SystemTime,
+        io::{self, BufRead, BufReader, IsTerminal, Read},
+        mem::MaybeUninit,
+        os::unix::ffi::OsStrExt,
+        path::{Component, Path, PathBuf},
+        process::{self, Child, Command, ExitStatus, Stdio},
+        sync::{LazyLock, Mutex, PoisonError, mpsc},
+        thread,
+        time::{Duration, Instant, SystemTime},
     };
 
+    const DTB_MAGIC: u32 = 0xd00dfeed;
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+    const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0];
+    const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+    const SQUASHFS_MAGIC: [u8; 4] = *b"hsqs";
+    const MODULE_SIGNATURE_MAGIC: &[u8] = b"~Module signature appended~\n";
+    const MODULE_SIGNATURE_PKCS7: u8 = 2;
+    const OCI_EIF_MEDIA_TYPE: &str = "application/vnd.aws.nitro.enclaves.eif";
+    const OCI_CONFIG_MEDIA_TYPE: &str = "application/vnd.aws.nitro.enclaves.eif.config.v1+json";
+    const INTOTO_STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+    const INTOTO_PREDICATE_TYPE: &str =
+        concat!(env!("CARGO_PKG_REPOSITORY"), "/eif-measurements/v1");
+    /// Modes of the directory and file entries in the initrd. These are fixed rather than taken
+    /// from the source files, so that the initrd (and therefore the measurements) does not depend
+    /// on the permissions of the inputs on the build host.
+    const DIR_MODE: u32 = 0o40755;
+    const FILE_MODE: u32 = 0o100755;
+    /// Size of the chunks inputs are read in when hashing them.
+    const HASH_CHUNK_SIZE: usize = 1 << 20;
+    /// Delay before the first retry of a failed read, increased by as much on every retry.
+    const MODULE_READ_RETRY_DELAY: Duration = Duration::from_millis(100);
+    /// A `{{KEY}}` reference to a template variable.
+    static TEMPLATE_VAR: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap());
+    /// Existing EIFs at least this large are only overwritten after confirmation.
+    const OVERWRITE_PROMPT_SIZE: u64 = 64 << 20;
+    /// Printed by the kernel when it executes /init from the initrd.
+    const PROBE_BOOT_MARKER: &str = "Run /init as init process";
+    const PROBE_BOOT_TIMEOUT: Duration = Duration::from_secs(60);
+
     #[derive(Clone, Debug, ValueEnum)]
     pub enum Arch {
         #[clap(name = "x86_64")]
@@ -61,38 +177,598 @@ pub mod build {
         Aarch64,
     }
 
-    struct Initrd {
-        path: PathBuf,
-        init: PathBuf,
+    /// Free space required on the filesystems written to by the build.
+    #[derive(Clone, Debug)]
+    pub enum FreeSpace {
+        Bytes(u64),
+        /// Estimated from the size of the inputs.
+        Auto,
+    }
+
+    impl std::str::FromStr for FreeSpace {
+        type Err = std::num::ParseIntError;
+
+        fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+            match s {
+                "auto" => Ok(Self::Auto),
+                bytes => bytes.parse().map(Self::Bytes),
+            }
+        }
+    }
+
+    /// Order of the module (and extra file) entries within the initrd.
+    #[derive(Clone, Debug, ValueEnum)]
+    pub enum SortEntries {
+        /// Sort by entry name.
+        #[clap(name = "name")]
+        Name,
+        /// Sort by entry size, smallest first.
+        #[clap(name = "size")]
+        Size,
+        /// Keep the order the entries were provided in.
+        #[clap(name = "none")]
+        None,
+    }
+
+    pub(super) struct Initrd {
+        pub(super) path: PathBuf,
+        init: Option<PathBuf>,
         modules: Vec<PathBuf>,
+        max_module_size: Option<u64>,
+        extra_files: Vec<ExtraFile>,
+        sort_entries: SortEntries,
+        entry_prefix: Option<String>,
+        prune_empty_dirs: bool,
+        modules_kernel_version: Option<String>,
+        module_read_retries: u32,
+        /// Warnings raised while resolving the initrd contents.
+        pub(super) warnings: Vec<String>,
     }
 
-    impl TryFrom<&BuildArgs> for Initrd {
-        type Error = anyhow::Error;
+    /// A file generated at build time and written to the initrd alongside init and the modules.
+    struct ExtraFile {
+        name: String,
+        contents: Vec<u8>,
+    }
 
-        fn try_from(args: &BuildArgs) -> Result<Self> {
-            let modules: Vec<PathBuf> = {
-                let json_str = fs::read_to_string(&args.kernel_modules).context(format!(
-                    "unable to read {:?} to string",
-                    args.kernel_modules
-                ))?;
+    impl Initrd {
+        /// Resolve the contents of an initrd to be written to `path`.
+        pub(super) fn new(args: &InitrdOptions, path: PathBuf) -> Result<Self> {
+            let mut modules = args.modules()?;
+            let mut warnings = Vec::new();
 
-                serde_json::from_str(&json_str).context(format!(
-                    "unable to deserialize {:?} to JSON array",
-                    args.kernel_modules
-                ))?
-            };
+            if args.allow_missing_modules {
+                let listed = modules.len();
+                modules.retain(|m| match File::open(m) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warnings.push(format!("skipping kernel module {}: {e}", m.display()));
+                        false
+                    }
+                });
+
+                let skipped = listed - modules.len();
+                if skipped > 0 {
+                    if modules.is_empty() {
+                        return Err(anyhow!("all {listed} listed kernel module(s) are missing"));
+                    }
+                    warnings.push(format!("skipped {skipped} of {listed} kernel module(s)"));
+                }
+            }
+
+            // --require-module-signatures and --trusted-keys require each other.
+            if let Some(keys) = &args.trusted_keys {
+                let trusted = trusted_keys(keys)?;
+
+                let failed: Vec<String> = modules
+                    .iter()
+                    .filter_map(|m| {
+                        verify_module_signature(m, &trusted)
+                            .err()
+                            .map(|e| format!("{}: {e:#}", m.display()))
+                    })
+                    .collect();
+                if !failed.is_empty() {
+                    return Err(anyhow!(
+                        "{} kernel module(s) failed signature verification:\n\t{}",
+                        failed.len(),
+                        failed.join("\n\t")
+                    ));
+                }
+            }
 
             Ok(Self {
-                path: args.initrd.clone(),
-                init: args.init.clone(),
+                path,
+                init: args.init_path().map(Path::to_path_buf),
                 modules,
+                max_module_size: args.max_module_size,
+                extra_files: Vec::new(),
+                sort_entries: args.sort_entries.clone(),
+                entry_prefix: args.entry_prefix.as_deref().map(entry_prefix).transpose()?,
+                prune_empty_dirs: args.prune_empty_dirs,
+                modules_kernel_version: args
+                    .modules_kernel_version
+                    .as_deref()
+                    .map(kernel_version)
+                    .transpose()?,
+                module_read_retries: args.module_read_retries,
+                warnings,
+            })
+        }
+    }
+
+    /// JSON-serialized list of kernel modules to include in the initrd.
+    pub type Modules = Vec<PathBuf>;
+
+    /// Read a file, retrying up to `retries` times (with an increasing delay) if reading fails
+    /// with a transient error.
+    fn read_with_retries(path: &Path, retries: u32) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            match fs::read(path) {
+                Ok(contents) => return Ok(contents),
+                Err(e) if attempt < retries && is_transient(&e) => {
+                    attempt += 1;
+                    thread::sleep(MODULE_READ_RETRY_DELAY * attempt);
+                }
+                Err(e) => {
+                    return Err(e).context(format!(
+                        "unable to read {:?} (attempt {} of {})",
+                        path,
+                        attempt + 1,
+                        retries + 1
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Whether an I/O error may succeed when retried, as opposed to e.g. a missing file or
+    /// insufficient permissions.
+    fn is_transient(e: &io::Error) -> bool {
+        matches!(
+            e.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        ) || matches!(
+            e.raw_os_error(),
+            Some(libc::EIO | libc::ETIMEDOUT | libc::EAGAIN)
+        )
+    }
+
+    /// Validate that a kernel version is usable as a single /lib/modules directory name.
+    fn kernel_version(version: &str) -> Result<String> {
+        let mut components = Path::new(version).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if !version.contains('/') => Ok(version.to_string()),
+            _ => Err(anyhow!(
+                "kernel version {version:?} must be a single directory name"
+            )),
+        }
+    }
+
+    /// Validate that an entry prefix is a safe relative path, normalizing trailing separators.
+    fn entry_prefix(prefix: &str) -> Result<String> {
+        let path = Path::new(prefix);
+        let safe = path.components().next().is_some()
+            && path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !safe {
+            return Err(anyhow!(
+                "entry prefix {prefix:?} must be a relative path without \".\" or \"..\" components"
+            ));
+        }
+
+        Ok(prefix.trim_end_matches('/').to_string())
+    }
+
+    /// Read the JSON-serialized list of kernel modules to include in the initrd. gzip and zstd
+    /// compressed lists are detected by their magic bytes and decompressed transparently.
+    fn read_modules(path: &Path) -> Result<Modules> {
+        let json = read_decompressed(path)?;
+
+        serde_json::from_slice(&json)
+            .context(format!("unable to deserialize {:?} to JSON array", path))
+    }
+
+    /// Name of the kernel module at `path`. Module names are compared with "-" and "_" treated
+    /// alike, as the kernel does.
+    fn module_name(path: &str) -> String {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let name = name.split(".ko").next().unwrap_or(name);
+
+        name.replace('-', "_")
+    }
+
+    /// Read the file at `path` (a kernel module or modules list), decompressing it if it is gzip
+    /// or zstd compressed. xz compressed files are rejected, as xz is not supported.
+    pub(super) fn read_decompressed(path: &Path) -> Result<Vec<u8>> {
+        let contents = fs::read(path).context(format!("unable to read from {:?}", path))?;
+
+        if contents.starts_with(&XZ_MAGIC) {
+            return Err(anyhow!(
+                "{:?} is xz-compressed, which is not supported (use gzip or zstd)",
+                path
+            ));
+        }
+
+        Ok(if contents.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(contents.as_slice())
+                .read_to_end(&mut decompressed)
+                .context(format!("unable to decompress gzip-compressed {:?}", path))?;
+            decompressed
+        } else if contents.starts_with(&ZSTD_MAGIC) {
+            zstd::decode_all(contents.as_slice())
+                .context(format!("unable to decompress zstd-compressed {:?}", path))?
+        } else {
+            contents
+        })
+    }
+
+    /// Read the kernel module at `path`, decompressed. Fails rather than returning bytes that are
+    /// not an ELF object (e.g. an unsupported compression), which would otherwise be silently
+    /// read as having no modinfo or signature.
+    fn read_module(path: &Path) -> Result<Vec<u8>> {
+        let module = read_decompressed(path)?;
+        if !module.starts_with(&ELF_MAGIC) {
+            return Err(anyhow!(
+                "{:?} is not an ELF object, the module compression is unsupported",
+                path
+            ));
+        }
+
+        Ok(module)
+    }
+
+    /// Read the trusted certificates from `path`, either PEM (one or more certificates) or a
+    /// single DER certificate such as the kernel's signing_key.x509.
+    fn trusted_keys(path: &Path) -> Result<Stack<X509>> {
+        let contents = fs::read(path).context(format!("unable to read from {:?}", path))?;
+        let certs = match X509::stack_from_pem(&contents) {
+            Ok(certs) if !certs.is_empty() => certs,
+            _ => vec![
+                X509::from_der(&contents)
+                    .context(format!("unable to parse certificates from {:?}", path))?,
+            ],
+        };
+
+        let mut stack = Stack::new().context("unable to allocate certificate stack")?;
+        for cert in certs {
+            stack
+                .push(cert)
+                .context("unable to add certificate to stack")?;
+        }
+
+        Ok(stack)
+    }
+
+    /// Verify the PKCS#7 signature appended to the kernel module at `path` (as written by the
+    /// kernel's sign-file) against the trusted certificates.
+    fn verify_module_signature(path: &Path, trusted: &Stack<X509>) -> Result<()> {
+        // The signature is appended before compression, so it can only be found once decompressed.
+        let module = read_module(path)?;
+
+        let rest = module
+            .strip_suffix(MODULE_SIGNATURE_MAGIC)
+            .ok_or(anyhow!("module is unsigned"))?;
+        // struct module_signature: algo, hash, id_type, signer_len, key_id_len, pad[3], and
+        // the big-endian length of the signature preceding it.
+        let (rest, info) = rest
+            .split_at_checked(rest.len().saturating_sub(12))
+            .filter(|(_, info)| info.len() == 12)
+            .ok_or(anyhow!("module signature is truncated"))?;
+        if info[2] != MODULE_SIGNATURE_PKCS7 {
+            return Err(anyhow!("module signature is not PKCS#7"));
+        }
+        let len = u32::from_be_bytes([info[8], info[9], info[10], info[11]]) as usize;
+        let (data, signature) = rest
+            .split_at_checked(rest.len().saturating_sub(len))
+            .filter(|(_, signature)| signature.len() == len)
+            .ok_or(anyhow!("module signature is truncated"))?;
+
+        let mut cms =
+            CmsContentInfo::from_der(signature).context("unable to parse module signature")?;
+        cms.verify(
+            Some(trusted),
+            None,
+            Some(data),
+            None,
+            CMSOptions::BINARY | CMSOptions::NOINTERN | CMSOptions::NO_SIGNER_CERT_VERIFY,
+        )
+        .context("module signature does not verify against the trusted keys")
+    }
+
+    /// Names of the modules the kernel module at `path` depends on, read from the "depends="
+    /// field of its modinfo. gzip and zstd compressed modules are decompressed first.
+    fn module_depends(path: &Path) -> Result<Vec<String>> {
+        let contents = read_module(path)?;
+
+        const FIELD: &[u8] = b"\0depends=";
+        let Some(start) = contents
+            .windows(FIELD.len())
+            .position(|w| w == FIELD)
+            .map(|idx| idx + FIELD.len())
+        else {
+            return Ok(Vec::new());
+        };
+        let len = contents[start..]
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(contents.len() - start);
+
+        Ok(String::from_utf8_lossy(&contents[start..start + len])
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(module_name)
+            .collect())
+    }
+
+    /// Generate the modules.dep and modules.order files for the modules (given by file name) in
+    /// a /lib/modules/<version> directory. modules.dep lists the complete dependencies of each
+    /// module, in the format written by depmod.
+    fn modules_dep(modules: &[(&str, &PathBuf)]) -> Result<(String, String)> {
+        let mut depends = BTreeMap::new();
+        for (file_name, path) in modules {
+            depends.insert(module_name(file_name), (*file_name, module_depends(path)?));
+        }
+
+        // `visiting` holds the modules whose dependencies are being visited, to detect cycles.
+        fn visit<'a>(
+            name: &str,
+            depends: &BTreeMap<String, (&'a str, Vec<String>)>,
+            visiting: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<()> {
+            let (file_name, dependencies) = &depends[name];
+            if order.contains(file_name) {
+                return Ok(());
+            }
+            if visiting.contains(file_name) {
+                return Err(anyhow!(
+                    "dependency cycle between kernel modules {} and {file_name}",
+                    visiting.last().unwrap_or(file_name)
+                ));
+            }
+
+            visiting.push(file_name);
+            for dependency in dependencies {
+                if !depends.contains_key(dependency) {
+                    return Err(anyhow!(
+                        "kernel module {file_name} depends on {dependency}, which is not included"
+                    ));
+                }
+                visit(dependency, depends, visiting, order)?;
+            }
+            visiting.pop();
+            order.push(file_name);
+
+            Ok(())
+        }
+
+        let (mut dep, mut order) = (String::new(), String::new());
+        for (file_name, _) in modules {
+            // Each module's dependencies in load order, followed by the module itself.
+            let mut load = Vec::new();
+            visit(
+                &module_name(file_name),
+                &depends,
+                &mut Vec::new(),
+                &mut load,
+            )?;
+            load.pop();
+
+            // depmod lists the dependency to be loaded first last.
+            load.reverse();
+            dep.push_str(&format!("{file_name}:"));
+            for dependency in load {
+                dep.push_str(&format!(" {dependency}"));
+            }
+            dep.push('\n');
+            order.push_str(&format!("{file_name}\n"));
+        }
+
+        Ok((dep, order))
+    }
+
+    /// Resolve the device aliases listed in `aliases` to kernel modules via the modules.alias and
+    /// modules.dep files in `dir`, as modprobe would. Every module is preceded by its
+    /// dependencies, in the order they must be loaded.
+    fn resolve_aliases(aliases: &Path, dir: &Path) -> Result<Modules> {
+        let read = |path: &Path| {
+            fs::read_to_string(path).context(format!("unable to read from {:?}", path))
+        };
+        let alias_file = dir.join("modules.alias");
+        let known: Vec<(String, String)> = read(&alias_file)?
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some("alias"), Some(pattern), Some(module)) => {
+                        Some((pattern.to_string(), module_name(module)))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let mut deps: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+        for line in read(&dir.join("modules.dep"))?.lines() {
+            let Some((path, dependencies)) = line.split_once(':') else {
+                continue;
+            };
+            let dependencies = dependencies.split_whitespace().map(module_name).collect();
+
+            deps.insert(module_name(path), (path.to_string(), dependencies));
+        }
+
+        fn visit(
+            name: &str,
+            deps: &BTreeMap<String, (String, Vec<String>)>,
+            seen: &mut Vec<String>,
+            modules: &mut Vec<String>,
+        ) -> Result<()> {
+            if seen.iter().any(|s| s == name) {
+                return Ok(());
+            }
+            seen.push(name.to_string());
+
+            let (path, dependencies) = deps
+                .get(name)
+                .with_context(|| format!("module {name} is not listed in modules.dep"))?;
+            // modprobe loads the dependencies listed in modules.dep last to first.
+            for dependency in dependencies.iter().rev() {
+                visit(dependency, deps, seen, modules)?;
+            }
+            modules.push(path.clone());
+
+            Ok(())
+        }
+
+        let mut seen = Vec::new();
+        let mut modules = Vec::new();
+        for alias in read(aliases)?.lines().map(str::trim) {
+            if alias.is_empty() || alias.starts_with('#') {
+                continue;
+            }
+
+            let matched: Vec<&String> = known
+                .iter()
+                .filter(|(pattern, _)| glob_match(pattern.as_bytes(), alias.as_bytes()))
+                .map(|(_, module)| module)
+                .collect();
+            if matched.is_empty() {
+                return Err(anyhow!(
+                    "no module in {} matches alias {alias}",
+                    alias_file.display()
+                ));
+            }
+
+            for module in matched {
+                visit(module, &deps, &mut seen, &mut modules)?;
+            }
+        }
+
+        Ok(modules.into_iter().map(|m| dir.join(m)).collect())
+    }
+
+    /// Match `text` against a shell-style glob `pattern` supporting `*`, `?` and `[...]`
+    /// character classes, as used by modules.alias.
+    fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+            Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+            Some((b'[', rest)) => {
+                let Some(end) = rest.iter().skip(1).position(|&c| c == b']').map(|i| i + 1) else {
+                    return text.first() == Some(&b'[') && glob_match(rest, &text[1..]);
+                };
+                let Some(&c) = text.first() else {
+                    return false;
+                };
+
+                let (negated, class) = match rest[0] {
+                    b'!' | b'^' => (true, &rest[1..end]),
+                    _ => (false, &rest[..end]),
+                };
+                let mut matched = false;
+                let mut i = 0;
+                while i < class.len() {
+                    if i + 2 < class.len() && class[i + 1] == b'-' {
+                        matched |= (class[i]..=class[i + 2]).contains(&c);
+                        i += 3;
+                    } else {
+                        matched |= class[i] == c;
+                        i += 1;
+                    }
+                }
+
+                matched != negated && glob_match(&rest[end + 1..], &text[1..])
+            }
+            Some((&c, rest)) => text.first() == Some(&c) && glob_match(rest, &text[1..]),
+        }
+    }
+
+    /// Insert a directory entry for every parent directory not already created by a preceding
+    /// entry, as parent directories must precede their contents in the archive.
+    fn with_parents(entries: Vec<Entry>) -> Vec<Entry> {
+        let mut dirs: Vec<String> = Vec::new();
+        let mut result = Vec::new();
+
+        for entry in entries {
+            let parents: Vec<&Path> = Path::new(entry.name())
+                .ancestors()
+                .skip(1)
+                .filter(|p| !p.as_os_str().is_empty())
+                .collect();
+            for parent in parents.into_iter().rev() {
+                let parent = format!("{}", parent.display());
+                if !dirs.contains(&parent) {
+                    dirs.push(parent.clone());
+                    result.push(Entry::Dir { name: parent });
+                }
+            }
+
+            if let Entry::Dir { name } = &entry {
+                if dirs.contains(name) {
+                    continue;
+                }
+                dirs.push(name.clone());
+            }
+            result.push(entry);
+        }
+
+        result
+    }
+
+    /// Remove directory entries that do not (transitively) contain any files.
+    fn pruned(entries: Vec<Entry>) -> Vec<Entry> {
+        let files: Vec<String> = entries
+            .iter()
+            .filter(|e| !matches!(e, Entry::Dir { .. }))
+            .map(|e| e.name().to_string())
+            .collect();
+
+        entries
+            .into_iter()
+            .filter(|e| match e {
+                Entry::Dir { name } => files.iter().any(|f| f.starts_with(&format!("{name}/"))),
+                _ => true,
             })
+            .collect()
+    }
+
+    /// An entry of the initrd CPIO archive.
+    enum Entry {
+        Dir { name: String },
+        File { name: String, path: PathBuf },
+        Generated { name: String, contents: Vec<u8> },
+    }
+
+    impl Entry {
+        fn name_mut(&mut self) -> &mut String {
+            match self {
+                Self::Dir { name } | Self::File { name, .. } | Self::Generated { name, .. } => name,
+            }
+        }
+
+        fn name(&self) -> &str {
+            match self {
+                Self::Dir { name } | Self::File { name, .. } | Self::Generated { name, .. } => name,
+            }
         }
     }
 
     impl Initrd {
-        fn build(&mut self) -> Result<()> {
+        pub(super) fn build(&mut self) -> Result<()> {
+            let mut entries = self.entries()?;
+
+            if let Some(prefix) = &self.entry_prefix {
+                for entry in entries.iter_mut() {
+                    let name = entry.name_mut();
+                    *name = format!("{prefix}/{name}");
+                }
+            }
+
+            record_output(&self.path);
             let mut file = OpenOptions::new()
                 .read(true)
                 .write(true)
@@ -101,16 +777,51 @@ pub mod build {
                 .open(self.path.clone())
                 .context(format!("unable to create/open {:?}", self.path))?;
 
-            self.write_file("init", &self.init.clone(), &mut file)?;
+            let mut entries = with_parents(entries);
+            if self.prune_empty_dirs {
+                entries = pruned(entries);
+            }
+
+            for entry in entries {
+                match entry {
+                    Entry::Dir { name } => self
+                        .write_dir(&name, &mut file)
+                        .context(format!("unable to create directory {:?}", name))?,
+                    Entry::File { name, path } => self.write_file(&name, &path, &mut file)?,
+                    Entry::Generated { name, contents } => self
+                        .write_entry(
+                            &name,
+                            contents.len() as u64,
+                            &mut contents.as_slice(),
+                            &mut file,
+                        )
+                        .context(format!("unable to write {:?} to CPIO archive", name))?,
+                }
+            }
 
-            let mods_dir = NewcBuilder::new("krun_linux_mods")
-                .mode(0o40755)
-                .set_mode_file_type(cpio::newc::ModeFileType::Directory);
-            let writer = mods_dir.write(&mut file, 0);
-            writer.finish().context(
-                "unable to create directory to store configurable enclave kernel modules",
-            )?;
+            let _ = trailer(&file).context("unable to write trailer entry to CPIO archive")?;
+
+            Ok(())
+        }
 
+        /// Assemble the entries of the archive, in the order they are written.
+        fn entries(&self) -> Result<Vec<Entry>> {
+            let mut entries = Vec::new();
+            if let Some(init) = &self.init {
+                entries.push(Entry::File {
+                    name: "init".to_string(),
+                    path: init.clone(),
+                });
+            }
+            let modules_dir = match &self.modules_kernel_version {
+                Some(version) => format!("lib/modules/{version}"),
+                None => "krun_linux_mods".to_string(),
+            };
+            entries.push(Entry::Dir {
+                name: modules_dir.clone(),
+            });
+
+            let mut modules = Vec::new();
             for entry in self.modules.iter() {
                 let file_osstr = entry.file_name().context(format!(
                     "unable to get OS file name of {:?}",
@@ -120,44 +831,222 @@ pub mod build {
                     "unable to get file name string of {:?}",
                     entry.as_path()
                 ))?;
+                let size = fs::metadata(entry)
+                    .context(format!("unable to get metadata of {:?}", entry.as_path()))?
+                    .len();
+
+                if let Some(max) = self.max_module_size
+                    && size > max
+                {
+                    return Err(anyhow!(
+                        "kernel module {} is {} bytes, exceeding the maximum module size of {} bytes",
+                        file_name,
+                        size,
+                        max
+                    ));
+                }
 
-                self.write_file(&format!("krun_linux_mods/{}", file_name), entry, &mut file)?;
+                modules.push((file_name, size, entry));
             }
 
-            let _ = trailer(&file).context("unable to write trailer entry to CPIO archive")?;
+            match self.sort_entries {
+                SortEntries::Name => modules.sort_by(|a, b| a.0.cmp(b.0)),
+                SortEntries::Size => modules.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0))),
+                SortEntries::None => (),
+            }
+
+            if self.modules_kernel_version.is_some() {
+                let modules: Vec<(&str, &PathBuf)> = modules
+                    .iter()
+                    .map(|(name, _, path)| (*name, *path))
+                    .collect();
+                let (dep, order) = modules_dep(&modules)?;
+
+                entries.extend([
+                    Entry::Generated {
+                        name: format!("{modules_dir}/modules.dep"),
+                        contents: dep.into_bytes(),
+                    },
+                    Entry::Generated {
+                        name: format!("{modules_dir}/modules.order"),
+                        contents: order.into_bytes(),
+                    },
+                ]);
+            }
+
+            entries.extend(
+                modules
+                    .into_iter()
+                    .map(|(file_name, _, entry)| Entry::File {
+                        name: format!("{modules_dir}/{file_name}"),
+                        path: entry.clone(),
+                    }),
+            );
+
+            let mut extra_files: Vec<&ExtraFile> = self.extra_files.iter().collect();
+            match self.sort_entries {
+                SortEntries::Name => extra_files.sort_by(|a, b| a.name.cmp(&b.name)),
+                SortEntries::Size => extra_files.sort_by(|a, b| {
+                    a.contents
+                        .len()
+                        .cmp(&b.contents.len())
+                        .then(a.name.cmp(&b.name))
+                }),
+                SortEntries::None => (),
+            }
+
+            entries.extend(extra_files.into_iter().map(|extra| Entry::Generated {
+                name: extra.name.clone(),
+                contents: extra.contents.clone(),
+            }));
+
+            Ok(entries)
+        }
+
+        fn write_dir(&self, name: &str, file: &mut File) -> Result<()> {
+            let dir = NewcBuilder::new(name)
+                .mode(DIR_MODE)
+                .set_mode_file_type(cpio::newc::ModeFileType::Directory);
+            let writer = dir.write(file, 0);
+            writer.finish()?;
 
             Ok(())
         }
 
+        /// Stream the file at `path` into the archive. Sparse files are not preserved as such:
+        /// holes are read back as zeros and written densely, so the archive (and therefore the
+        /// measurements) is identical to that of an equivalent non-sparse file.
         fn write_file(&self, name: &str, path: &Path, file: &mut File) -> Result<()> {
+            // When retrying, the file is read fully before anything is written, so that a failed
+            // attempt never leaves a partial entry in the archive.
+            if self.module_read_retries > 0 {
+                let contents = read_with_retries(path, self.module_read_retries)?;
+                return self
+                    .write_entry(name, contents.len() as u64, &mut contents.as_slice(), file)
+                    .context(format!("unable to write {:?} to CPIO archive", path));
+            }
+
+            let mut source = File::open(path).context(format!("unable to open {:?}", path))?;
+            let size = source
+                .metadata()
+                .context(format!("unable to get metadata of {:?}", path))?
+                .len();
+
+            self.write_entry(name, size, &mut source, file)
+                .context(format!("unable to write {:?} to CPIO archive", path))
+        }
+
+        fn write_entry(
+            &self,
+            name: &str,
+            size: u64,
+            contents: &mut impl Read,
+            file: &mut File,
+        ) -> Result<()> {
             let cpio = NewcBuilder::new(name)
-                .mode(0o100755)
+                .mode(FILE_MODE)
                 .dev_major(3)
                 .dev_minor(1);
 
-            let contents = fs::read(path).context(format!("unable to read from {:?}", path))?;
-
             let mut writer = cpio.write(
                 file,
-                contents
-                    .len()
-                    .try_into()
-                    .context(format!("unable to convert file size of {:?} to u32", path))?,
+                size.try_into()
+                    .context("unable to convert file size to u32")?,
             );
-            io::copy(&mut contents.as_slice(), &mut writer).context(format!(
-                "unable to copy contents of {:?} to CPIO archive writer",
-                path
-            ))?;
+            // Never copy more than was declared in the header, and fail rather than produce a
+            // corrupt archive if the contents turn out to be shorter (e.g. a truncated file).
+            let copied = io::copy(&mut contents.take(size), &mut writer)
+                .context("unable to copy contents to CPIO archive writer")?;
+            if copied != size {
+                return Err(anyhow!(
+                    "{name} is truncated: copied {copied} of {size} declared bytes"
+                ));
+            }
 
-            writer.finish().context(format!(
-                "unable to complete write of {:?} to CPIO archive",
-                path
-            ))?;
+            writer
+                .finish()
+                .context("unable to complete write to CPIO archive")?;
 
             Ok(())
         }
     }
 
+    /// Options configuring the contents of the krun-awsnitro initrd.
+    #[derive(Args)]
+    pub(super) struct InitrdOptions {
+        /// krun-awsnitro init binary.
+        #[arg(long, default_value = "/etc/krun-awsnitro/init")]
+        init: PathBuf,
+        /// Do not include an init in the initrd, e.g. when it is provided by another initrd
+        /// layer.
+        #[arg(long)]
+        no_init: bool,
+        /// JSON-serialized kernel modules to include in the enclave image.
+        #[arg(long, required_unless_present = "modules_from_aliases")]
+        kernel_modules: Option<PathBuf>,
+        /// File listing device aliases (e.g. PCI modaliases), one per line, to resolve to the
+        /// kernel modules to include (and their dependencies) using --modules-dir.
+        #[arg(long, conflicts_with = "kernel_modules", requires = "modules_dir")]
+        modules_from_aliases: Option<PathBuf>,
+        /// Kernel modules directory containing modules.alias and modules.dep, which module paths
+        /// are relative to.
+        #[arg(long, requires = "modules_from_aliases")]
+        modules_dir: Option<PathBuf>,
+        /// Maximum size (in bytes) of an individual kernel module. Unlimited if not provided.
+        #[arg(long)]
+        max_module_size: Option<u64>,
+        /// Warn about and skip kernel modules that cannot be read, rather than failing. At
+        /// least one listed module must remain.
+        #[arg(long)]
+        allow_missing_modules: bool,
+        /// Order of the module and extra file entries in the initrd. Changing the order changes
+        /// the initrd, and therefore the EIF measurements.
+        #[arg(long, default_value = "name")]
+        sort_entries: SortEntries,
+        /// Relative directory to place every initrd entry under.
+        #[arg(long)]
+        entry_prefix: Option<String>,
+        /// Write kernel modules to lib/modules/<VERSION> rather than krun_linux_mods, along with
+        /// generated modules.dep and modules.order files for modprobe.
+        #[arg(long)]
+        modules_kernel_version: Option<String>,
+        /// Require every kernel module to carry a valid appended signature from one of the
+        /// --trusted-keys.
+        #[arg(long, requires = "trusted_keys")]
+        require_module_signatures: bool,
+        /// Certificates (PEM, or a single DER certificate) trusted to sign kernel modules.
+        #[arg(long, requires = "require_module_signatures")]
+        trusted_keys: Option<PathBuf>,
+        /// Remove directories that contain no files from the initrd.
+        #[arg(long)]
+        prune_empty_dirs: bool,
+        /// Number of times to retry reading a file into the initrd after a transient I/O error
+        /// (e.g. EIO or a timeout on a network mount). Other errors fail immediately.
+        #[arg(long, default_value_t = 0)]
+        module_read_retries: u32,
+    }
+
+    impl InitrdOptions {
+        /// The init binary to include in the initrd, unless --no-init is given.
+        fn init_path(&self) -> Option<&Path> {
+            (!self.no_init).then_some(self.init.as_path())
+        }
+
+        /// The kernel modules to include in the initrd, either listed in --kernel-modules or
+        /// resolved from --modules-from-aliases.
+        fn modules(&self) -> Result<Modules> {
+            match (
+                &self.kernel_modules,
+                &self.modules_from_aliases,
+                &self.modules_dir,
+            ) {
+                (Some(list), _, _) => read_modules(list),
+                (None, Some(aliases), Some(dir)) => resolve_aliases(aliases, dir),
+                _ => Err(anyhow!("no kernel modules specified")),
+            }
+        }
+    }
+
     /// Arguments to configure the EIF file built for use in krun-awsnitro.
     #[derive(Parser)]
     pub(super) struct BuildArgs {
@@ -167,86 +1056,2986 @@ pub mod build {
         /// Enclave kernel.
         #[arg(short, long)]
         kernel: PathBuf,
+        /// Expected SHA384 digest (hex) of the kernel. The build fails before doing anything else
+        /// if the kernel does not match.
+        #[arg(long)]
+        kernel_sha384: Option<String>,
         /// Enclave kernel cmdline.
         #[arg(short, long, default_value = "/etc/krun-awsnitro/cmdline")]
         cmdline: PathBuf,
-        /// krun-awsnitro init binary.
-        #[arg(long, default_value = "/etc/krun-awsnitro/init")]
-        init: PathBuf,
-        /// JSON-serialized kernel modules to include in the enclave image.
+        /// Regular expression the cmdline must match. May be repeated.
+        #[arg(long, value_parser = Regex::new)]
+        cmdline_require: Vec<Regex>,
+        /// Regular expression the cmdline must not match. May be repeated.
+        #[arg(long, value_parser = Regex::new)]
+        cmdline_forbid: Vec<Regex>,
+        #[command(flatten)]
+        initrd_options: InitrdOptions,
+        /// Directory to write the EIF (image.eif), initrd (bootstrap-initrd.img) and PCR lockfile
+        /// (measurements.json) to. Paths given explicitly with --path, --initrd and
+        /// --pcr-lockfile take precedence.
         #[arg(long)]
-        kernel_modules: PathBuf,
-        /// Path to write the krun-awsnitro initrd.
-        #[arg(long, default_value = "/etc/krun-awsnitro/bootstrap-initrd.img")]
-        initrd: PathBuf,
-        /// Path to write the EIF image to.
-        #[arg(short, long, default_value = "/etc/krun-awsnitro/krun-awsnitro.eif")]
-        path: PathBuf,
+        output_dir: Option<PathBuf>,
+        /// Path to write the krun-awsnitro initrd [default: /etc/krun-awsnitro/bootstrap-initrd.img].
+        #[arg(long)]
+        initrd: Option<PathBuf>,
+        /// Path to write the EIF image to [default: /etc/krun-awsnitro/krun-awsnitro.eif].
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+        /// Path to write the final EIF size (in bytes) to.
+        #[arg(long)]
+        emit_length: Option<PathBuf>,
+        /// Directory to additionally write the EIF to as an OCI image layout.
+        #[arg(long)]
+        oci_layout: Option<PathBuf>,
+        /// PEM-formatted certificate used to sign the EIF.
+        #[arg(long, requires = "signing_key")]
+        signing_certificate: Option<PathBuf>,
+        /// PEM-formatted private key used to sign the EIF.
+        #[arg(long, requires = "signing_certificate")]
+        signing_key: Option<PathBuf>,
+        /// Do not warn when building an unsigned EIF.
+        #[arg(long)]
+        allow_unsigned: bool,
+        /// Record the absolute, resolved paths of all inputs in the EIF custom metadata. This
+        /// makes the metadata specific to the build host.
+        #[arg(long)]
+        canonicalize_paths: bool,
+        /// Record the commit, branch and dirty state of the git repository in the current
+        /// directory in the EIF custom metadata.
+        #[arg(long)]
+        embed_git: bool,
+        /// Compute the EIF measurements without writing the initrd or EIF.
+        #[arg(long)]
+        dry_run: bool,
+        /// Without writing the initrd or EIF, compute the EIF measurements directly from the
+        /// component files and check that they match those computed by the EIF builder.
+        #[arg(long, conflicts_with = "dry_run")]
+        measure_from_sources: bool,
+        /// Show the progress of hashing each input with --measure-from-sources. Only shown when
+        /// stderr is a terminal. A normal build cannot report progress, as the EIF builder reads
+        /// and hashes the kernel and ramdisks in a single call.
+        #[arg(long, requires = "measure_from_sources")]
+        progress: bool,
+        /// Shell command to run after a successful build. The EIF path and PCRs are passed in the
+        /// EIF_PATH and EIF_PCR0, EIF_PCR1, ... environment variables. Its output goes to
+        /// stderr with --print-pcr.
+        #[arg(long)]
+        post_build_hook: Option<String>,
+        /// Print a table summarizing the build. Shown by default when stdout is a terminal.
+        #[arg(long)]
+        summary_table: bool,
+        /// Do not print informational output, such as the build summary table.
+        #[arg(short, long, conflicts_with = "summary_table")]
+        quiet: bool,
+        /// Treat every warning raised during the build as an error.
+        #[arg(long)]
+        strict: bool,
+        /// Minimum free space (in bytes, or "auto" to estimate it from the size of the inputs)
+        /// required on the filesystems the build writes to before starting.
+        #[arg(long)]
+        min_free_space: Option<FreeSpace>,
+        /// Overwrite a large existing EIF without asking for confirmation.
+        #[arg(short, long)]
+        yes: bool,
+        /// Print only the value of the given PCR to stdout, e.g. for `PCR0=$(... --print-pcr 0)`.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=2))]
+        print_pcr: Option<u8>,
+        /// Expected PCR3 (IAM role) value to record alongside the measurements. It is not
+        /// derived from the EIF.
+        #[arg(long)]
+        pcr3: Option<String>,
+        /// Expected PCR4 (instance ID) value to record alongside the measurements. It is not
+        /// derived from the EIF.
+        #[arg(long)]
+        pcr4: Option<String>,
+        /// Fail the build if the EIF is larger than this reference EIF (by more than --tolerance).
+        #[arg(long)]
+        fail_if_larger_than: Option<PathBuf>,
+        /// Percentage by which the EIF may exceed the size of --fail-if-larger-than.
+        #[arg(long, default_value_t = 0.0, requires = "fail_if_larger_than")]
+        tolerance: f64,
+        /// JSON file recording the EIF measurements. Written on build, and compared against the
+        /// computed measurements with --dry-run.
+        #[arg(long)]
+        pcr_lockfile: Option<PathBuf>,
+        /// Rebuild whenever the kernel, cmdline, init, modules or signing inputs change.
+        #[arg(long)]
+        watch: bool,
+        /// JSON file to write a description of every build input and output to, along with the
+        /// identity info and measurements of the EIF.
+        #[arg(long)]
+        companion_json: Option<PathBuf>,
+        /// File to write an in-toto statement to, with the EIF as its subject and the
+        /// measurements and hashed build inputs as its predicate.
+        #[arg(long)]
+        intoto_predicate: Option<PathBuf>,
+        /// Abort the build (removing the outputs it has started writing) if it takes longer than
+        /// the given number of seconds.
+        #[arg(long, conflicts_with = "watch")]
+        pub(super) timeout: Option<u64>,
+        /// Write an os-release style file describing the image identity into the initrd. PCR0 is
+        /// not included, as the file itself is part of the measured initrd. The file records the
+        /// BUILD_TIME, so PCR0 and PCR1 change on every build made with this option.
+        #[arg(long)]
+        generate_image_release: bool,
+        /// Path within the initrd to write the generated image release file to.
+        #[arg(long, default_value = "etc/image-release")]
+        image_release_path: String,
+        /// Device tree blob to include in the initrd (aarch64 only).
+        #[arg(long)]
+        dtb: Option<PathBuf>,
+        /// Path within the initrd to write the device tree blob to.
+        #[arg(long, default_value = "boot/dtb")]
+        dtb_path: String,
+        /// Record only the basenames of input paths in the EIF metadata, never full paths.
+        #[arg(long)]
+        redact_paths: bool,
+        /// squashfs image to add as a second ramdisk after the bootstrap initrd.
+        #[arg(long)]
+        squashfs: Option<PathBuf>,
+        /// Briefly boot the kernel and initrd under QEMU and fail the build if init is not
+        /// reached. This is best-effort, and skipped with a warning if QEMU is not installed or
+        /// there is no /init (--no-init, --entry-prefix). The probe appends loglevel=7 to the
+        /// cmdline so that "quiet" does not hide the kernel messages it looks for.
+        #[arg(long)]
+        probe_boot: bool,
+        /// Check that GNU cpio lists exactly the entries of the built initrd. Skipped with a
+        /// warning if cpio is not installed.
+        #[arg(long)]
+        verify_with_system_cpio: bool,
+        /// Also write the kernel, cmdline and initrd the EIF was built from next to it, with
+        /// the EIF's extension replaced by .kernel, .cmdline and .initrd.
+        #[arg(long)]
+        split_output: bool,
+        /// Directory of templates to render with the --var variables. A template named
+        /// `cmdline` replaces the --cmdline file, and every other template is written to the
+        /// initrd at its path relative to the directory.
+        #[arg(long)]
+        template_dir: Option<PathBuf>,
+        /// Variable (KEY=VALUE) substituted for `{{KEY}}` in the --template-dir templates.
+        #[arg(
+            long = "var",
+            value_name = "KEY=VALUE",
+            value_parser = template_var,
+            requires = "template_dir"
+        )]
+        vars: Vec<(String, String)>,
     }
 
-    pub(super) fn build(args: BuildArgs) -> Result<()> {
-        let build_info = build_info(&args)?;
-
-        let cmdline = fs::read_to_string(&args.cmdline)
-            .with_context(|| format!("unable to read cmdline from {}", args.cmdline.display()))?;
+    impl BuildArgs {
+        /// Path to write the EIF to.
+        fn eif_path(&self) -> PathBuf {
+            self.output_path(&self.path, "image.eif")
+                .unwrap_or_else(|| PathBuf::from("/etc/krun-awsnitro/krun-awsnitro.eif"))
+        }
+
+        /// File the cmdline is read from: the `cmdline` template of --template-dir if there is
+        /// one, otherwise --cmdline.
+        fn cmdline_path(&self) -> PathBuf {
+            match &self.template_dir {
+                Some(dir) if dir.join("cmdline").is_file() => dir.join("cmdline"),
+                _ => self.cmdline.clone(),
+            }
+        }
+
+        /// Templates rendered with --template-dir.
+        fn templates(&self) -> Result<Vec<PathBuf>> {
+            match &self.template_dir {
+                Some(dir) => template_files(dir),
+                None => Ok(Vec::new()),
+            }
+        }
+
+        /// Paths to write the kernel, cmdline and initrd to with --split-output.
+        fn split_output_paths(&self) -> [PathBuf; 3] {
+            let eif = self.eif_path();
+            ["kernel", "cmdline", "initrd"].map(|extension| eif.with_extension(extension))
+        }
+
+        /// Path to write the initrd to.
+        fn initrd_path(&self) -> PathBuf {
+            self.output_path(&self.initrd, "bootstrap-initrd.img")
+                .unwrap_or_else(|| PathBuf::from("/etc/krun-awsnitro/bootstrap-initrd.img"))
+        }
+
+        /// Path of the PCR lockfile, if any.
+        fn lockfile_path(&self) -> Option<PathBuf> {
+            self.output_path(&self.pcr_lockfile, "measurements.json")
+        }
+
+        /// The explicitly-given path of an output, or its conventional `name` in --output-dir.
+        fn output_path(&self, explicit: &Option<PathBuf>, name: &str) -> Option<PathBuf> {
+            explicit
+                .clone()
+                .or_else(|| self.output_dir.as_ref().map(|dir| dir.join(name)))
+        }
+    }
+
+    /// Outputs created or truncated by this run so far. Only these are removed when the build is
+    /// aborted, so that the outputs of a previous build are kept until they are overwritten.
+    static WRITTEN_OUTPUTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+    /// Record that an output is about to be created or truncated.
+    fn record_output(path: &Path) {
+        WRITTEN_OUTPUTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(path.to_path_buf());
+    }
+
+    /// Outputs created or truncated by the build so far.
+    pub(super) fn written_outputs() -> Vec<PathBuf> {
+        WRITTEN_OUTPUTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Processes (the --probe-boot VMM, the --post-build-hook shell) the build is waiting on,
+    /// killed if the build is aborted.
+    static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    /// Spawn a child process, recording it until it is reaped with `reap`.
+    fn spawn_child(command: &mut Command) -> io::Result<Child> {
+        let child = command.spawn()?;
+        CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(child.id());
+
+        Ok(child)
+    }
+
+    /// Wait for a child spawned with `spawn_child`, and stop tracking it.
+    fn reap(child: &mut Child) -> io::Result<ExitStatus> {
+        // Wait for the child to exit without reaping it, so that its PID cannot be reused (and
+        // killed by kill_children) before it is no longer tracked.
+        let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+        // SAFETY: `info` is a valid siginfo_t for waitid(2) to write to.
+        unsafe {
+            libc::waitid(
+                libc::P_PID,
+                child.id() as libc::id_t,
+                info.as_mut_ptr(),
+                libc::WEXITED | libc::WNOWAIT,
+            );
+        }
+        CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|pid| *pid != child.id());
+
+        child.wait()
+    }
+
+    /// Kill the child processes the build is waiting on. Processes they spawned themselves (e.g.
+    /// commands run by the post-build hook) are not tracked, and may survive.
+    pub(super) fn kill_children() {
+        for pid in CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            // SAFETY: kill(2) has no memory safety requirements. The PIDs are of unreaped
+            // children (see reap), so they cannot have been reused by another process.
+            unsafe {
+                libc::kill(*pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+
+    /// Temporary file, removed when dropped.
+    struct RemoveOnDrop(PathBuf);
+
+    impl Drop for RemoveOnDrop {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Temporary path the initrd is written to when only computing measurements.
+    fn dry_run_initrd_path() -> PathBuf {
+        env::temp_dir().join(format!(
+            "krun-awsnitro-dry-run-{}-initrd.img",
+            process::id()
+        ))
+    }
+
+    pub(super) fn build(args: BuildArgs) -> Result<()> {
+        // Checked again by each --watch rebuild, but fail before touching anything here.
+        if let Some(expected) = &args.kernel_sha384 {
+            check_kernel_sha384(&args.kernel, expected)?;
+        }
+
+        let eif = args.eif_path();
+        if !args.dry_run && !args.measure_from_sources && !args.yes && !confirm_overwrite(&eif)? {
+            return Err(anyhow!("not overwriting {}", eif.display()));
+        }
+
+        if let Some(dir) = &args.output_dir
+            && !args.dry_run
+            && !args.measure_from_sources
+        {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create output directory {}", dir.display()))?;
+        }
+
+        if let Some(min) = &args.min_free_space {
+            check_free_space(&args, min)?;
+        }
+
+        if args.watch {
+            return watch(&args);
+        }
+
+        let measurements = build_eif(&args)?;
+        if args.print_pcr.is_some() && !args.dry_run && !args.measure_from_sources {
+            print_measurements(&args, &measurements)?;
+        }
+
+        Ok(())
+    }
+
+    /// Print the measurements as JSON, or only the value of the PCR selected with --print-pcr.
+    fn print_measurements(args: &BuildArgs, measurements: &BTreeMap<String, String>) -> Result<()> {
+        if let Some(pcr) = args.print_pcr {
+            let value = measurements
+                .get(&format!("PCR{pcr}"))
+                .with_context(|| format!("PCR{pcr} was not computed"))?;
+            println!("{value}");
+
+            return Ok(());
+        }
+
+        let json = serde_json::to_string_pretty(measurements)
+            .context("unable to serialize EIF measurements to JSON")?;
+        println!("{json}");
+
+        Ok(())
+    }
+
+    /// Fail unless every filesystem the build writes to has at least the required free space.
+    fn check_free_space(args: &BuildArgs, min: &FreeSpace) -> Result<()> {
+        let required = match min {
+            FreeSpace::Bytes(bytes) => *bytes,
+            // The inputs end up in both the initrd and the EIF.
+            FreeSpace::Auto => {
+                let options = &args.initrd_options;
+                let mut modules = options.modules()?;
+                // Modules that the build will skip (and warn about) take no space.
+                if options.allow_missing_modules {
+                    modules.retain(|m| File::open(m).is_ok());
+                }
+
+                let mut inputs = vec![args.kernel.clone()];
+                inputs.extend(options.init_path().map(Path::to_path_buf));
+                inputs.extend(modules);
+                inputs.extend(args.squashfs.iter().cloned());
+                inputs.extend(args.dtb.iter().cloned());
+
+                let mut total = 0;
+                for input in inputs {
+                    total += fs::metadata(&input)
+                        .with_context(|| format!("unable to get metadata of {}", input.display()))?
+                        .len();
+                }
+
+                total * 2
+            }
+        };
+
+        let mut dirs = match args.dry_run || args.measure_from_sources {
+            true => vec![env::temp_dir()],
+            false => [args.eif_path(), args.initrd_path()]
+                .iter()
+                .map(|path| match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                    _ => PathBuf::from("."),
+                })
+                .collect(),
+        };
+        dirs.dedup();
+
+        for dir in dirs {
+            let available = free_space(&dir)?;
+            if available < required {
+                return Err(anyhow!(
+                    "insufficient free space in {}: {required} bytes required, {available} available",
+                    dir.display()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Space available to unprivileged users on the filesystem containing `dir`.
+    fn free_space(dir: &Path) -> Result<u64> {
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .with_context(|| format!("invalid path {}", dir.display()))?;
+        let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+        // SAFETY: `path` is a valid NUL-terminated string and `stat` is only read after
+        // statvfs() has successfully initialized it.
+        let stat = unsafe {
+            if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| format!("unable to get free space of {}", dir.display()));
+            }
+            stat.assume_init()
+        };
+
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    /// Ask for confirmation before overwriting an existing EIF larger than
+    /// OVERWRITE_PROMPT_SIZE. Always confirmed when not running interactively.
+    fn confirm_overwrite(path: &Path) -> Result<bool> {
+        let Ok(metadata) = fs::metadata(path) else {
+            return Ok(true);
+        };
+
+        if metadata.len() < OVERWRITE_PROMPT_SIZE
+            || !io::stdin().is_terminal()
+            || !io::stderr().is_terminal()
+        {
+            return Ok(true);
+        }
+
+        eprint!(
+            "{} already exists ({} bytes), overwrite? [y/N] ",
+            path.display(),
+            metadata.len()
+        );
+
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .context("unable to read confirmation from stdin")?;
+
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    /// Build the EIF once, returning its measurements.
+    fn build_eif(args: &BuildArgs) -> Result<BTreeMap<String, String>> {
+        let mut warnings = Vec::new();
+        let result = assemble(args, &mut warnings);
+
+        if !warnings.is_empty() {
+            eprintln!("Warnings ({}):", warnings.len());
+            for warning in &warnings {
+                eprintln!("\t{warning}");
+            }
+        }
+
+        if result.is_ok() && args.strict && !warnings.is_empty() {
+            return Err(strict_error(&warnings));
+        }
+
+        if let (Ok(measurements), Some(hook)) = (&result, &args.post_build_hook)
+            && !args.dry_run
+            && !args.measure_from_sources
+        {
+            post_build_hook(hook, args, measurements)?;
+        }
+
+        result
+    }
+
+    fn strict_error(warnings: &[String]) -> anyhow::Error {
+        anyhow!(
+            "{} warning(s) treated as errors with --strict",
+            warnings.len()
+        )
+    }
+
+    /// Run the post-build hook through the shell, passing it the EIF path (EIF_PATH), the initrd
+    /// path (EIF_INITRD_PATH) and each PCR (EIF_PCR0, ...) in its environment.
+    fn post_build_hook(
+        hook: &str,
+        args: &BuildArgs,
+        measurements: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", hook])
+            .env("EIF_PATH", args.eif_path())
+            .env("EIF_INITRD_PATH", args.initrd_path());
+        for (key, value) in measurements.iter().filter(|(k, _)| k.starts_with("PCR")) {
+            command.env(format!("EIF_{key}"), value);
+        }
+        // Keep stdout to the single PCR value, for scripts capturing it.
+        if args.print_pcr.is_some() {
+            command.stdout(Stdio::from(io::stderr()));
+        }
+
+        let status = spawn_child(&mut command)
+            .and_then(|mut child| reap(&mut child))
+            .with_context(|| format!("unable to run post-build hook {hook:?}"))?;
+        if !status.success() {
+            return Err(anyhow!("post-build hook {hook:?} failed ({status})"));
+        }
+
+        Ok(())
+    }
+
+    /// Assemble the initrd and EIF, collecting any warnings raised along the way.
+    fn assemble(args: &BuildArgs, warnings: &mut Vec<String>) -> Result<BTreeMap<String, String>> {
+        if let Some(expected) = &args.kernel_sha384 {
+            check_kernel_sha384(&args.kernel, expected)?;
+        }
+        let external = external_pcrs(args)?;
+
+        let mut initrd = Initrd::new(&args.initrd_options, args.initrd_path())
+            .context("unable to build initrd")?;
+        warnings.append(&mut initrd.warnings);
+
+        let custom_info = custom_info(args, &initrd, warnings)?;
+        let build_info = build_info(args, custom_info)?;
+
+        let mut templates = match &args.template_dir {
+            Some(dir) => render_templates(dir, &args.vars, warnings)
+                .with_context(|| format!("unable to render templates in {}", dir.display()))?,
+            None => Vec::new(),
+        };
+        let cmdline = match templates.iter().position(|(name, _)| name == "cmdline") {
+            Some(i) => templates.remove(i).1,
+            None => fs::read_to_string(&args.cmdline).with_context(|| {
+                format!("unable to read cmdline from {}", args.cmdline.display())
+            })?,
+        };
+        check_cmdline(&cmdline, &args.cmdline_require, &args.cmdline_forbid)?;
 
         let flags = match args.arch {
             Arch::X86_64 => 0,
             Arch::Aarch64 => EIF_HDR_ARCH_ARM64,
         };
 
-        let mut initrd = Initrd::try_from(&args).context("unable to build initrd")?;
+        if args.generate_image_release {
+            initrd.extra_files.push(ExtraFile {
+                name: args.image_release_path.trim_start_matches('/').to_string(),
+                contents: image_release(&build_info, &args.arch).into_bytes(),
+            });
+        }
+
+        initrd
+            .extra_files
+            .extend(templates.into_iter().map(|(name, contents)| ExtraFile {
+                name,
+                contents: contents.into_bytes(),
+            }));
+
+        if let Some(dtb) = &args.dtb {
+            initrd.extra_files.push(ExtraFile {
+                name: args.dtb_path.trim_start_matches('/').to_string(),
+                contents: read_dtb(dtb, &args.arch)?,
+            });
+        }
+
+        if args.dry_run || args.measure_from_sources {
+            initrd.path = dry_run_initrd_path();
+        }
+        // Removed on every return from here on, including errors.
+        let _temporary =
+            (args.dry_run || args.measure_from_sources).then(|| RemoveOnDrop(initrd.path.clone()));
         initrd.build().context("unable to build initrd")?;
 
+        if args.verify_with_system_cpio {
+            verify_with_system_cpio(&initrd.path, warnings)?;
+        }
+
+        let sign_info = match (&args.signing_certificate, &args.signing_key) {
+            (Some(cert), Some(key)) => Some(
+                SignEnclaveInfo::new(
+                    &format!("{}", cert.display()),
+                    &format!("{}", key.display()),
+                )
+                .map_err(|e| anyhow!(e))
+                .context("unable to read EIF signing certificate/key")?,
+            ),
+            _ => None,
+        };
+        let signed = sign_info.is_some();
+
         let mut build = EifBuilder::new(
             &args.kernel,
-            cmdline,
-            None,
+            cmdline.clone(),
+            sign_info,
             Sha384::new(),
             flags,
-            build_info,
+            build_info.clone(),
         );
 
-        build.add_ramdisk(Path::new(&args.initrd));
+        build.add_ramdisk(&initrd.path);
+
+        if let Some(squashfs) = &args.squashfs {
+            let digest = squashfs_digest(squashfs)?;
+            build.add_ramdisk(squashfs);
+
+            if !args.quiet {
+                eprintln!(
+                    "{}: SHA384 {digest} (measured in PCR0 and PCR2)",
+                    squashfs.display()
+                );
+            }
+        }
+
+        if args.measure_from_sources {
+            let mut ramdisks = vec![initrd.path.as_path()];
+            ramdisks.extend(args.squashfs.as_deref());
+
+            return measure_from_sources(args, &cmdline, &ramdisks).and_then(|mut sources| {
+                let builder = builder_measurements(&mut build)?;
+                if sources != builder {
+                    return Err(anyhow!(
+                        "measurements computed from sources {:?} do not match the EIF builder {:?}",
+                        sources,
+                        builder
+                    ));
+                }
+                sources.extend(external.clone());
+                print_measurements(args, &sources)?;
+
+                Ok(sources)
+            });
+        }
+
+        if args.dry_run {
+            // A lockfile in --output-dir is only compared against once it has been written.
+            let lockfile = args
+                .lockfile_path()
+                .filter(|path| args.pcr_lockfile.is_some() || path.exists());
+            return dry_run(&mut build, args, lockfile.as_deref(), &external);
+        }
+
+        let eif_path = args.eif_path();
+
+        if args.probe_boot {
+            probe_boot(args, &cmdline, &initrd.path, warnings)?;
+        }
+
+        if !signed && !args.allow_unsigned {
+            warnings.push(format!(
+                "{} is being built without a --signing-key, the EIF is unsigned and PCR8 will be all-zero",
+                eif_path.display()
+            ));
+        }
+
+        // Every warning is known by now, so fail before writing the EIF (and the outputs derived
+        // from it) rather than leaving publishable artifacts behind.
+        if args.strict && !warnings.is_empty() {
+            let _ = fs::remove_file(&initrd.path);
+            return Err(strict_error(warnings));
+        }
 
+        record_output(&eif_path);
         let mut output = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .truncate(true)
-            .open(args.path)
+            .open(&eif_path)
             .context("failed to create output file")?;
 
-        build.write_to(&mut output);
+        let mut measurements = build.write_to(&mut output);
+        measurements.extend(external);
+
+        if args.split_output {
+            let [kernel, cmdline_path, initrd_path] = args.split_output_paths();
+            for path in [&kernel, &cmdline_path, &initrd_path] {
+                record_output(path);
+            }
+            fs::copy(&args.kernel, &kernel)
+                .with_context(|| format!("unable to write kernel to {}", kernel.display()))?;
+            fs::write(&cmdline_path, &cmdline).with_context(|| {
+                format!("unable to write cmdline to {}", cmdline_path.display())
+            })?;
+            fs::copy(&initrd.path, &initrd_path)
+                .with_context(|| format!("unable to write initrd to {}", initrd_path.display()))?;
+        }
+
+        if let Some(emit_length) = &args.emit_length {
+            let len = output
+                .metadata()
+                .with_context(|| format!("unable to get metadata of {}", eif_path.display()))?
+                .len();
+
+            record_output(emit_length);
+            fs::write(emit_length, format!("{len}\n")).with_context(|| {
+                format!("unable to write EIF length to {}", emit_length.display())
+            })?;
+        }
+
+        if let Some(reference) = &args.fail_if_larger_than {
+            check_size(&eif_path, reference, args.tolerance)?;
+        }
+
+        if let Some(lockfile) = &args.lockfile_path() {
+            let json = serde_json::to_string_pretty(&measurements)
+                .context("unable to serialize EIF measurements to JSON")?;
+            record_output(lockfile);
+            fs::write(lockfile, json)
+                .with_context(|| format!("unable to write PCR lockfile {}", lockfile.display()))?;
+        }
+
+        if let Some(dir) = &args.oci_layout {
+            write_oci_layout(dir, &eif_path, &build_info, &measurements).with_context(|| {
+                format!("unable to write OCI image layout to {}", dir.display())
+            })?;
+        }
+
+        if let Some(companion) = &args.companion_json {
+            write_companion(
+                companion,
+                args,
+                &initrd,
+                &build_info,
+                &measurements,
+                warnings,
+            )
+            .with_context(|| {
+                format!("unable to write companion JSON to {}", companion.display())
+            })?;
+        }
+
+        if let Some(statement) = &args.intoto_predicate {
+            write_intoto_statement(statement, args, &initrd, &measurements).with_context(|| {
+                format!(
+                    "unable to write in-toto statement to {}",
+                    statement.display()
+                )
+            })?;
+        }
+
+        // Shown by default when run interactively, unless stdout is used for --print-pcr.
+        let summary = args.summary_table
+            || (io::stdout().is_terminal() && args.print_pcr.is_none() && !args.watch);
+        if summary && !args.quiet {
+            print_summary(args, &initrd, &eif_path, &measurements)?;
+        }
+
+        Ok(measurements)
+    }
+
+    /// List the initrd with `cpio -t`, failing if cpio cannot read it or does not list the same
+    /// entries (in the same order) as the archive contains.
+    fn verify_with_system_cpio(path: &Path, warnings: &mut Vec<String>) -> Result<()> {
+        let archive = fs::read(path).context(format!("unable to read {:?}", path))?;
+        let expected: Vec<String> = eif::cpio_entries(&archive)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+
+        let input = File::open(path).context(format!("unable to open {:?}", path))?;
+        let output = match Command::new("cpio")
+            .args(["-t", "--quiet"])
+            .stdin(input)
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warnings.push("cpio not found, skipping --verify-with-system-cpio".to_string());
+                return Ok(());
+            }
+            Err(e) => return Err(e).context("unable to run cpio"),
+        };
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "cpio -t was unable to read {} ({}): {}",
+                path.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let listed: Vec<&str> = stdout.lines().collect();
+        if let Some(i) = (0..expected.len().max(listed.len()))
+            .find(|&i| expected.get(i).map(String::as_str) != listed.get(i).copied())
+        {
+            return Err(anyhow!(
+                "cpio -t listing of {} differs from the archive at entry {i}: expected {:?}, listed {:?}",
+                path.display(),
+                expected.get(i),
+                listed.get(i)
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Boot the kernel and initrd under QEMU until the kernel reports running /init, failing if
+    /// it does not within PROBE_BOOT_TIMEOUT. Skipped with a warning if QEMU is not installed, or
+    /// if the initrd has no /init.
+    fn probe_boot(
+        args: &BuildArgs,
+        cmdline: &str,
+        initrd: &Path,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        // The kernel can only report running /init if the initrd has one at its root.
+        let options = &args.initrd_options;
+        if options.init_path().is_none() || options.entry_prefix.is_some() {
+            warnings.push(
+                "the initrd has no /init with --no-init or --entry-prefix, skipping --probe-boot"
+                    .to_string(),
+            );
+            return Ok(());
+        }
+
+        let (qemu, machine, console): (_, &[&str], _) = match args.arch {
+            Arch::X86_64 => ("qemu-system-x86_64", &[], "ttyS0"),
+            Arch::Aarch64 => (
+                "qemu-system-aarch64",
+                &["-M", "virt", "-cpu", "max"],
+                "ttyAMA0",
+            ),
+        };
+
+        let spawned = spawn_child(
+            Command::new(qemu)
+                .args(machine)
+                .args(["-m", "512", "-nographic", "-no-reboot", "-kernel"])
+                .arg(&args.kernel)
+                .arg("-initrd")
+                .arg(initrd)
+                .arg("-append")
+                // The marker is logged at info level, which a "quiet" cmdline would hide.
+                .arg(format!(
+                    "{} console={console} loglevel=7 panic=-1",
+                    cmdline.trim()
+                ))
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+        );
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warnings.push(format!("{qemu} not found, skipping --probe-boot"));
+                return Ok(());
+            }
+            Err(e) => return Err(e).context(format!("unable to run {qemu}")),
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        thread::spawn(move || {
+            for line in stdout.split(b'\n').map_while(|line| line.ok()) {
+                if tx
+                    .send(String::from_utf8_lossy(&line).into_owned())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + PROBE_BOOT_TIMEOUT;
+        let mut last = String::new();
+        let reached = loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) if line.contains(PROBE_BOOT_MARKER) => break Ok(()),
+                Ok(line) if !line.trim().is_empty() => last = line,
+                Ok(_) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => break Err("timed out"),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break Err("exited"),
+            }
+        };
+
+        let _ = child.kill();
+        let _ = reap(&mut child);
+
+        reached.map_err(|reason| {
+            anyhow!(
+                "boot probe under {qemu} {reason} before reaching init (last console output: {:?})",
+                last.trim()
+            )
+        })
+    }
+
+    /// Print a table summarizing the inputs, outputs and measurements of the build.
+    fn print_summary(
+        args: &BuildArgs,
+        initrd: &Initrd,
+        eif: &Path,
+        measurements: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let size = |path: &Path| -> Result<u64> {
+            Ok(fs::metadata(path)
+                .with_context(|| format!("unable to get metadata of {}", path.display()))?
+                .len())
+        };
+
+        let mut kernel =
+            File::open(&args.kernel).context(format!("unable to open {:?}", args.kernel))?;
+        let mut hasher = Sha384::new();
+        io::copy(&mut kernel, &mut hasher).context(format!("unable to hash {:?}", args.kernel))?;
+
+        let mut modules_size = 0;
+        for module in &initrd.modules {
+            modules_size += size(module)?;
+        }
+
+        let rows = [
+            ("Arch", format!("{:?}", args.arch).to_lowercase()),
+            ("Kernel", redacted(&args.kernel)?),
+            ("Kernel SHA384", format!("{:x}", hasher.finalize())),
+            (
+                "Modules",
+                format!("{} ({modules_size} bytes)", initrd.modules.len()),
+            ),
+            ("Initrd", format!("{} bytes", size(&initrd.path)?)),
+            ("EIF", format!("{} bytes", size(eif)?)),
+        ];
+        let pcrs = ["PCR0", "PCR1", "PCR2"]
+            .into_iter()
+            .map(|pcr| (pcr, measurements.get(pcr).cloned().unwrap_or_default()));
+
+        for (key, value) in rows.into_iter().chain(pcrs) {
+            println!("{key:<14} {value}");
+        }
 
         Ok(())
     }
 
-    fn build_info(args: &BuildArgs) -> Result<EifIdentityInfo> {
-        let kernel_name = {
-            let path = format!("{}", args.kernel.display());
+    /// PCRs populated at runtime rather than computed from the EIF (PCR3: IAM role, PCR4:
+    /// instance ID), as supplied on the command line. They are listed under "ExternalPCRs" to
+    /// distinguish them from the measured PCRs.
+    fn external_pcrs(args: &BuildArgs) -> Result<BTreeMap<String, String>> {
+        let mut pcrs = BTreeMap::new();
+        for (name, value) in [("PCR3", &args.pcr3), ("PCR4", &args.pcr4)] {
+            let Some(value) = value else {
+                continue;
+            };
+            if value.len() != 96 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(anyhow!(
+                    "{name} value {value:?} is not a hex-encoded SHA384 digest"
+                ));
+            }
 
-            let mut sub: Vec<String> = path.split('/').map(|s| s.to_string()).collect();
+            pcrs.insert(name.to_string(), value.to_ascii_lowercase());
+        }
 
-            sub.pop()
+        if !pcrs.is_empty() {
+            let names: Vec<&str> = pcrs.keys().map(String::as_str).collect();
+            pcrs.insert("ExternalPCRs".to_string(), names.join(","));
         }
-        .context("unable to get kernel name for EIF build info")?;
 
-        let datetime: DateTime<Utc> = SystemTime::now().into();
-        let version = env!("CARGO_PKG_VERSION").to_string();
+        Ok(pcrs)
+    }
 
-        Ok(EifIdentityInfo {
-            img_name: "krun-awsnitro-eif".to_string(),
-            img_version: "n/a".to_string(),
-            build_info: EifBuildInfo {
-                build_time: format!("{}", datetime),
-                build_tool: "krun-awsnitro-eif-ctl".to_string(),
-                build_tool_version: version,
-                img_os: "n/a".to_string(),
-                img_kernel: kernel_name,
+    /// Verify that the cmdline matches every `require` pattern and none of the `forbid` patterns.
+    fn check_cmdline(cmdline: &str, require: &[Regex], forbid: &[Regex]) -> Result<()> {
+        if let Some(re) = require.iter().find(|re| !re.is_match(cmdline)) {
+            return Err(anyhow!(
+                "cmdline {cmdline:?} does not match required pattern {:?}",
+                re.as_str()
+            ));
+        }
+
+        if let Some(re) = forbid.iter().find(|re| re.is_match(cmdline)) {
+            return Err(anyhow!(
+                "cmdline {cmdline:?} matches forbidden pattern {:?}",
+                re.as_str()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Verify that the SHA384 digest of the kernel matches the hex-encoded `expected` digest.
+    fn check_kernel_sha384(kernel: &Path, expected: &str) -> Result<()> {
+        let mut file = File::open(kernel).context(format!("unable to open {:?}", kernel))?;
+        let mut hasher = Sha384::new();
+        io::copy(&mut file, &mut hasher).context(format!("unable to hash {:?}", kernel))?;
+
+        let digest = format!("{:x}", hasher.finalize());
+        if !digest.eq_ignore_ascii_case(expected.trim()) {
+            return Err(anyhow!(
+                "kernel {} has SHA384 {digest}, expected {expected}",
+                kernel.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Fail if the EIF at `path` is more than `tolerance` percent larger than `reference`.
+    fn check_size(path: &Path, reference: &Path, tolerance: f64) -> Result<()> {
+        let size = |path: &Path| -> Result<u64> {
+            Ok(fs::metadata(path)
+                .with_context(|| format!("unable to get metadata of {}", path.display()))?
+                .len())
+        };
+        let (new, old) = (size(path)?, size(reference)?);
+
+        let delta = new as i64 - old as i64;
+        let percent = match old {
+            0 => 0.0,
+            _ => delta as f64 * 100.0 / old as f64,
+        };
+        eprintln!(
+            "EIF size: {new} bytes (reference {} is {old} bytes, delta {delta:+} bytes / {percent:+.2}%)",
+            reference.display()
+        );
+
+        if new as f64 > old as f64 * (1.0 + tolerance / 100.0) {
+            return Err(anyhow!(
+                "{} is larger than {} by more than {tolerance}%",
+                path.display(),
+                reference.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Write a JSON document describing every input and output of the build, along with the
+    /// identity info and measurements of the EIF.
+    fn write_companion(
+        path: &Path,
+        args: &BuildArgs,
+        initrd: &Initrd,
+        identity: &EifIdentityInfo,
+        measurements: &BTreeMap<String, String>,
+        warnings: &[String],
+    ) -> Result<()> {
+        let record = |path: &Path| -> Result<Value> {
+            let mut file = File::open(path).context(format!("unable to open {:?}", path))?;
+            let mut hasher = Sha384::new();
+            let size =
+                io::copy(&mut file, &mut hasher).context(format!("unable to hash {:?}", path))?;
+
+            Ok(json!({
+                "path": match args.redact_paths {
+                    true => redacted(path)?,
+                    false => format!("{}", path.display()),
+                },
+                "sha384": format!("{:x}", hasher.finalize()),
+                "size": size,
+            }))
+        };
+        let optional = |path: &Option<PathBuf>| -> Result<Value> {
+            path.as_deref().map(record).transpose().map(Value::from)
+        };
+
+        let modules = initrd
+            .modules
+            .iter()
+            .map(|m| record(m))
+            .collect::<Result<Vec<Value>>>()?;
+        let templates = args
+            .templates()?
+            .iter()
+            .map(|t| record(t))
+            .collect::<Result<Vec<Value>>>()?;
+
+        let companion = json!({
+            "tool": {
+                "name": identity.build_info.build_tool,
+                "version": identity.build_info.build_tool_version,
             },
-            docker_info: Value::Null,
-            custom_info: Value::Null,
-        })
+            "build_time": identity.build_info.build_time,
+            "inputs": {
+                "kernel": record(&args.kernel)?,
+                "cmdline": record(&args.cmdline_path())?,
+                "init": args.initrd_options.init_path().map(record).transpose()?,
+                "kernel_modules": optional(&args.initrd_options.kernel_modules)?,
+                "modules_from_aliases": optional(&args.initrd_options.modules_from_aliases)?,
+                "modules": modules,
+                "squashfs": optional(&args.squashfs)?,
+                "dtb": optional(&args.dtb)?,
+                "signing_certificate": optional(&args.signing_certificate)?,
+                "templates": templates,
+            },
+            "outputs": {
+                "eif": record(&args.eif_path())?,
+                "initrd": record(&initrd.path)?,
+            },
+            "identity": identity,
+            "measurements": measurements,
+            "warnings": warnings,
+        });
+
+        let json = serde_json::to_string_pretty(&companion)
+            .context("unable to serialize companion JSON")?;
+
+        record_output(path);
+        fs::write(path, json).context(format!("unable to write to {:?}", path))
+    }
+
+    /// Write an in-toto statement attesting to the EIF, whose predicate records the measurements
+    /// and the digests of the inputs (materials) the EIF was built from.
+    fn write_intoto_statement(
+        path: &Path,
+        args: &BuildArgs,
+        initrd: &Initrd,
+        measurements: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let name = |path: &Path| -> Result<String> {
+            match args.redact_paths {
+                true => redacted(path),
+                false => Ok(format!("{}", path.display())),
+            }
+        };
+
+        let options = &args.initrd_options;
+        let cmdline = args.cmdline_path();
+        let templates = args.templates()?;
+        let inputs = [
+            Some(args.kernel.as_path()),
+            Some(cmdline.as_path()),
+            options.init_path(),
+            options.kernel_modules.as_deref(),
+            options.modules_from_aliases.as_deref(),
+            args.squashfs.as_deref(),
+            args.dtb.as_deref(),
+            args.signing_certificate.as_deref(),
+        ];
+        let materials = inputs
+            .into_iter()
+            .flatten()
+            .chain(initrd.modules.iter().map(PathBuf::as_path))
+            .chain(
+                templates
+                    .iter()
+                    .map(PathBuf::as_path)
+                    .filter(|t| *t != cmdline),
+            )
+            .map(|path| {
+                Ok(json!({
+                    "uri": name(path)?,
+                    "digest": { "sha384": file_digest::<Sha384>(path)? },
+                }))
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        let eif = args.eif_path();
+        let eif_name = eif.file_name().unwrap_or(eif.as_os_str());
+        let statement = json!({
+            "_type": INTOTO_STATEMENT_TYPE,
+            "subject": [{
+                "name": eif_name.to_string_lossy(),
+                "digest": {
+                    "sha256": file_digest::<Sha256>(&eif)?,
+                    "sha384": file_digest::<Sha384>(&eif)?,
+                },
+            }],
+            "predicateType": INTOTO_PREDICATE_TYPE,
+            "predicate": {
+                "measurements": measurements,
+                "materials": materials,
+            },
+        });
+
+        let json = serde_json::to_string_pretty(&statement)
+            .context("unable to serialize in-toto statement")?;
+
+        record_output(path);
+        fs::write(path, json).context(format!("unable to write to {:?}", path))
+    }
+
+    /// Hex-encoded digest of the contents of a file.
+    fn file_digest<D: Digest + io::Write>(path: &Path) -> Result<String> {
+        let mut file = File::open(path).context(format!("unable to open {:?}", path))?;
+        let mut hasher = D::new();
+        io::copy(&mut file, &mut hasher).context(format!("unable to hash {:?}", path))?;
+
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect())
+    }
+
+    /// Rebuild the EIF whenever one of its inputs changes, printing the new measurements.
+    fn watch(args: &BuildArgs) -> Result<()> {
+        let poll = Duration::from_millis(500);
+
+        loop {
+            match build_eif(args) {
+                Ok(measurements) if !args.dry_run && !args.measure_from_sources => {
+                    print_measurements(args, &measurements)?
+                }
+                Ok(_) => (),
+                Err(e) => print_error(&e),
+            }
+
+            eprintln!("Watching for changes to build inputs...");
+            let built = input_mtimes(args);
+            while input_mtimes(args) == built {
+                thread::sleep(poll);
+            }
+
+            // Debounce bursts of writes (e.g. an editor saving, or a kernel build finishing)
+            // by waiting until the inputs are stable for a full poll interval.
+            let mut current = input_mtimes(args);
+            loop {
+                thread::sleep(poll);
+                let next = input_mtimes(args);
+                if next == current {
+                    break;
+                }
+                current = next;
+            }
+        }
+    }
+
+    /// Modification times of every input of the build. Missing inputs are recorded as `None`, so
+    /// that their (re)appearance is also treated as a change.
+    fn input_mtimes(args: &BuildArgs) -> Vec<(PathBuf, Option<SystemTime>)> {
+        let mut inputs = vec![args.kernel.clone(), args.cmdline_path()];
+        inputs.extend(args.initrd_options.init_path().map(Path::to_path_buf));
+        inputs.extend(args.initrd_options.kernel_modules.iter().cloned());
+        inputs.extend(args.initrd_options.modules_from_aliases.iter().cloned());
+        if let Some(dir) = &args.initrd_options.modules_dir {
+            inputs.extend([dir.join("modules.alias"), dir.join("modules.dep")]);
+        }
+        inputs.extend(args.squashfs.iter().cloned());
+        inputs.extend(args.dtb.iter().cloned());
+        inputs.extend(args.signing_certificate.iter().cloned());
+        inputs.extend(args.signing_key.iter().cloned());
+        if let Ok(templates) = args.templates() {
+            inputs.extend(templates);
+        }
+        if let Ok(modules) = args.initrd_options.modules() {
+            inputs.extend(modules);
+        }
+
+        inputs
+            .into_iter()
+            .map(|path| {
+                let mtime = fs::metadata(&path).and_then(|m| m.modified()).ok();
+                (path, mtime)
+            })
+            .collect()
+    }
+
+    /// Parse a KEY=VALUE template variable.
+    fn template_var(s: &str) -> Result<(String, String)> {
+        let (key, value) = s
+            .split_once('=')
+            .with_context(|| format!("{s:?} is not of the form KEY=VALUE"))?;
+        let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(anyhow!("{key:?} is not a valid template variable name"));
+        }
+
+        Ok((key.to_string(), value.to_string()))
+    }
+
+    /// Every file under the template directory, sorted by path.
+    fn template_files(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).context(format!("unable to read directory {:?}", dir))? {
+            let path = entry
+                .context(format!("unable to read directory {:?}", dir))?
+                .path();
+            match path.is_dir() {
+                true => files.extend(template_files(&path)?),
+                false => files.push(path),
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// Render every template under `dir`, substituting each `{{KEY}}` with the value of the
+    /// variable. Returns the rendered templates by their path relative to `dir`. Templates
+    /// referencing an undefined variable are an error, and unused variables a warning.
+    fn render_templates(
+        dir: &Path,
+        vars: &[(String, String)],
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<(String, String)>> {
+        let vars: BTreeMap<&str, &str> = vars
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let mut used = Vec::new();
+
+        let mut rendered = Vec::new();
+        for path in template_files(dir)? {
+            let name = path
+                .strip_prefix(dir)
+                .ok()
+                .and_then(Path::to_str)
+                .context(format!("unable to get relative path string of {:?}", path))?
+                .to_string();
+            let template =
+                fs::read_to_string(&path).context(format!("unable to read template {:?}", path))?;
+
+            let mut undefined = None;
+            let contents = TEMPLATE_VAR.replace_all(&template, |caps: &regex::Captures| {
+                let key = &caps[1];
+                match vars.get(key) {
+                    Some(value) => {
+                        used.push(key.to_string());
+                        value.to_string()
+                    }
+                    None => {
+                        undefined.get_or_insert_with(|| key.to_string());
+                        String::new()
+                    }
+                }
+            });
+            if let Some(key) = undefined {
+                return Err(anyhow!("template {name} uses undefined variable {key:?}"));
+            }
+
+            rendered.push((name, contents.into_owned()));
+        }
+
+        for key in vars.keys().filter(|key| !used.iter().any(|u| u == *key)) {
+            warnings.push(format!(
+                "template variable {key:?} is not used by any template"
+            ));
+        }
+
+        Ok(rendered)
+    }
+
+    /// Measurements of the EIF as computed by the EIF builder.
+    fn builder_measurements(build: &mut EifBuilder<Sha384>) -> Result<BTreeMap<String, String>> {
+        build.measure();
+        let signed = build.is_signed();
+
+        get_pcrs(
+            &mut build.image_hasher,
+            &mut build.bootstrap_hasher,
+            &mut build.customer_app_hasher,
+            &mut build.certificate_hasher,
+            Sha384::new(),
+            signed,
+        )
+        .map_err(|e| anyhow!(e))
+        .context("unable to compute EIF measurements")
+    }
+
+    /// Compute the measurements of the EIF directly from its component files, without the EIF
+    /// builder. Each PCR is extended once from zero: PCRn = SHA384(0^48 || SHA384(contents)).
+    fn measure_from_sources(
+        args: &BuildArgs,
+        cmdline: &str,
+        ramdisks: &[&Path],
+    ) -> Result<BTreeMap<String, String>> {
+        let progress = args.progress && io::stderr().is_terminal();
+        let hash_file = |hasher: &mut Sha384, path: &Path| -> Result<()> {
+            let mut file = File::open(path).context(format!("unable to open {:?}", path))?;
+            let total = file
+                .metadata()
+                .context(format!("unable to get metadata of {:?}", path))?
+                .len();
+
+            let mut chunk = vec![0u8; HASH_CHUNK_SIZE];
+            let (mut hashed, mut shown) = (0, None);
+            loop {
+                let len = file
+                    .read(&mut chunk)
+                    .context(format!("unable to hash {:?}", path))?;
+                if len == 0 {
+                    break;
+                }
+                hasher.update(&chunk[..len]);
+                hashed += len as u64;
+
+                let percent = hashed * 100 / total.max(1);
+                if progress && shown != Some(percent) {
+                    eprint!("\rhashing {}: {percent:>3}%", path.display());
+                    shown = Some(percent);
+                }
+            }
+            if progress {
+                eprintln!();
+            }
+
+            Ok(())
+        };
+        let extend = |hasher: Sha384| -> String {
+            let mut pcr = Sha384::new();
+            pcr.update([0u8; 48]);
+            pcr.update(hasher.finalize());
+
+            format!("{:x}", pcr.finalize())
+        };
+
+        let mut boot = Sha384::new();
+        hash_file(&mut boot, &args.kernel)?;
+        boot.update(cmdline.as_bytes());
+
+        let mut pcr0 = boot.clone();
+        for ramdisk in ramdisks {
+            hash_file(&mut pcr0, ramdisk)?;
+        }
+
+        let mut pcr1 = boot;
+        if let Some(ramdisk) = ramdisks.first() {
+            hash_file(&mut pcr1, ramdisk)?;
+        }
+
+        let mut pcr2 = Sha384::new();
+        for ramdisk in ramdisks.iter().skip(1) {
+            hash_file(&mut pcr2, ramdisk)?;
+        }
+
+        let mut measurements = BTreeMap::new();
+        measurements.insert("HashAlgorithm".to_string(), format!("{:?}", Sha384::new()));
+        measurements.insert("PCR0".to_string(), extend(pcr0));
+        measurements.insert("PCR1".to_string(), extend(pcr1));
+        measurements.insert("PCR2".to_string(), extend(pcr2));
+
+        if let Some(cert) = &args.signing_certificate {
+            let pem = fs::read(cert).context(format!("unable to read {:?}", cert))?;
+            let der = X509::from_pem(&pem)
+                .and_then(|cert| cert.to_der())
+                .context(format!("unable to parse signing certificate {:?}", cert))?;
+
+            let mut hasher = Sha384::new();
+            hasher.update(der);
+            measurements.insert("PCR8".to_string(), extend(hasher));
+        }
+
+        Ok(measurements)
+    }
+
+    /// Compute the measurements the EIF would have and either print them, or print how they differ
+    /// from those recorded in a PCR lockfile.
+    fn dry_run(
+        build: &mut EifBuilder<Sha384>,
+        args: &BuildArgs,
+        lockfile: Option<&Path>,
+        external: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut measurements = builder_measurements(build)?;
+        measurements.extend(external.clone());
+
+        let Some(lockfile) = lockfile.filter(|_| args.print_pcr.is_none()) else {
+            print_measurements(args, &measurements)?;
+
+            return Ok(measurements);
+        };
+
+        let locked: BTreeMap<String, String> = {
+            let json_str = fs::read_to_string(lockfile)
+                .context(format!("unable to read {:?} to string", lockfile))?;
+
+            serde_json::from_str(&json_str).context(format!(
+                "unable to deserialize {:?} to JSON object",
+                lockfile
+            ))?
+        };
+
+        let mut keys: Vec<&String> = measurements.keys().chain(locked.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            match (locked.get(key), measurements.get(key)) {
+                (Some(old), Some(new)) if old == new => println!("{key}: unchanged"),
+                (old, new) => {
+                    println!("{key}: changed");
+                    println!("\tlocked: {}", old.map(String::as_str).unwrap_or("<none>"));
+                    println!("\tnew:    {}", new.map(String::as_str).unwrap_or("<none>"));
+                }
+            }
+        }
+
+        Ok(measurements)
+    }
+
+    /// Write the EIF as a single-layer OCI artifact, with a config blob describing the identity
+    /// info and measurements of the image.
+    fn write_oci_layout(
+        dir: &Path,
+        eif: &Path,
+        identity: &EifIdentityInfo,
+        measurements: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let blobs = dir.join("blobs/sha256");
+        fs::create_dir_all(&blobs)
+            .with_context(|| format!("unable to create directory {}", blobs.display()))?;
+
+        let write_blob = |contents: &[u8], media_type: &str| -> Result<Value> {
+            let digest = format!("{:x}", Sha256::digest(contents));
+            let path = blobs.join(&digest);
+            fs::write(&path, contents)
+                .with_context(|| format!("unable to write OCI blob {}", path.display()))?;
+
+            Ok(json!({
+                "mediaType": media_type,
+                "digest": format!("sha256:{digest}"),
+                "size": contents.len(),
+            }))
+        };
+
+        let eif_contents =
+            fs::read(eif).with_context(|| format!("unable to read EIF {}", eif.display()))?;
+        let mut layer = write_blob(&eif_contents, OCI_EIF_MEDIA_TYPE)?;
+        if let Some(name) = eif.file_name() {
+            layer["annotations"] = json!({
+                "org.opencontainers.image.title": name.to_string_lossy(),
+            });
+        }
+
+        let config = serde_json::to_vec(&json!({
+            "identity": identity,
+            "measurements": measurements,
+        }))
+        .context("unable to serialize OCI config")?;
+        let config = write_blob(&config, OCI_CONFIG_MEDIA_TYPE)?;
+
+        let manifest = serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "artifactType": OCI_EIF_MEDIA_TYPE,
+            "config": config,
+            "layers": [layer],
+        }))
+        .context("unable to serialize OCI manifest")?;
+        let manifest = write_blob(&manifest, "application/vnd.oci.image.manifest.v1+json")?;
+
+        let index = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [manifest],
+        });
+        fs::write(dir.join("index.json"), index.to_string())
+            .context("unable to write OCI index.json")?;
+        fs::write(
+            dir.join("oci-layout"),
+            json!({ "imageLayoutVersion": "1.0.0" }).to_string(),
+        )
+        .context("unable to write OCI oci-layout file")?;
+
+        Ok(())
+    }
+
+    /// Validate that `path` is a squashfs image, returning its SHA384 digest.
+    fn squashfs_digest(path: &Path) -> Result<String> {
+        let contents = fs::read(path).context(format!("unable to read from {:?}", path))?;
+        if !contents.starts_with(&SQUASHFS_MAGIC) {
+            return Err(anyhow!(
+                "{} is not a squashfs image (missing superblock magic \"hsqs\")",
+                path.display()
+            ));
+        }
+
+        Ok(format!("{:x}", Sha384::digest(&contents)))
+    }
+
+    /// Read a device tree blob, validating that it is one and that the build is for aarch64.
+    fn read_dtb(path: &Path, arch: &Arch) -> Result<Vec<u8>> {
+        if !matches!(arch, Arch::Aarch64) {
+            return Err(anyhow!("--dtb is only supported for --arch aarch64"));
+        }
+
+        let dtb = fs::read(path).context(format!("unable to read from {:?}", path))?;
+        if !dtb.starts_with(&DTB_MAGIC.to_be_bytes()) {
+            return Err(anyhow!(
+                "{} is not a device tree blob (missing magic {:#x})",
+                path.display(),
+                DTB_MAGIC
+            ));
+        }
+
+        Ok(dtb)
+    }
+
+    /// Contents of the os-release style image release file.
+    fn image_release(identity: &EifIdentityInfo, arch: &Arch) -> String {
+        let arch = arch
+            .to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default();
+
+        format!(
+            "IMAGE_NAME=\"{}\"\nIMAGE_VERSION=\"{}\"\nBUILD_TIME=\"{}\"\nARCH=\"{}\"\n",
+            identity.img_name, identity.img_version, identity.build_info.build_time, arch
+        )
+    }
+
+    /// Custom metadata recorded in the EIF identity info. Null if no option requires any.
+    fn custom_info(args: &BuildArgs, initrd: &Initrd, warnings: &mut Vec<String>) -> Result<Value> {
+        let mut info = Map::new();
+
+        if args.canonicalize_paths {
+            let options = &args.initrd_options;
+            let canonical = |path: &Path| -> Result<Value> {
+                let path = fs::canonicalize(path)
+                    .with_context(|| format!("unable to canonicalize path {}", path.display()))?;
+
+                Ok(Value::String(match args.redact_paths {
+                    true => redacted(&path)?,
+                    false => format!("{}", path.display()),
+                }))
+            };
+
+            let modules = initrd
+                .modules
+                .iter()
+                .map(|m| canonical(m))
+                .collect::<Result<Vec<Value>>>()?;
+            let templates = args
+                .templates()?
+                .iter()
+                .map(|t| canonical(t))
+                .collect::<Result<Vec<Value>>>()?;
+
+            info.insert(
+                "inputs".to_string(),
+                json!({
+                    "kernel": canonical(&args.kernel)?,
+                    "cmdline": canonical(&args.cmdline_path())?,
+                    "init": options.init_path().map(canonical).transpose()?,
+                    "kernel_modules": options.kernel_modules.as_deref().map(canonical).transpose()?,
+                    "modules_from_aliases": options
+                        .modules_from_aliases
+                        .as_deref()
+                        .map(canonical)
+                        .transpose()?,
+                    "modules": modules,
+                    "squashfs": args.squashfs.as_deref().map(canonical).transpose()?,
+                    "dtb": args.dtb.as_deref().map(canonical).transpose()?,
+                    "signing_certificate": args
+                        .signing_certificate
+                        .as_deref()
+                        .map(canonical)
+                        .transpose()?,
+                    "templates": templates,
+                }),
+            );
+        }
+
+        if args.embed_git {
+            match git_info() {
+                Ok(git) => {
+                    info.insert("git".to_string(), git);
+                }
+                Err(e) => warnings.push(format!("not embedding git metadata: {e}")),
+            }
+        }
+
+        Ok(match info.is_empty() {
+            true => Value::Null,
+            false => Value::Object(info),
+        })
+    }
+
+    /// Commit, branch and dirty state of the git repository in the current directory.
+    fn git_info() -> Result<Value> {
+        let git = |args: &[&str]| -> Result<String> {
+            let output = Command::new("git")
+                .args(args)
+                .output()
+                .context("unable to run git")?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "git {} failed: {}",
+                    args.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+
+        Ok(json!({
+            "commit": git(&["rev-parse", "HEAD"])?,
+            "branch": git(&["rev-parse", "--abbrev-ref", "HEAD"])?,
+            "dirty": !git(&["status", "--porcelain"])?.is_empty(),
+        }))
+    }
+
+    /// Strip every leading directory from `path`, leaving only its basename.
+    fn redacted(path: &Path) -> Result<String> {
+        let name = path
+            .file_name()
+            .ok_or(anyhow!("{} has no file name", path.display()))?;
+
+        Ok(name.to_string_lossy().into_owned())
+    }
+
+    fn build_info(args: &BuildArgs, custom_info: Value) -> Result<EifIdentityInfo> {
+        let kernel_name =
+            redacted(&args.kernel).context("unable to get kernel name for EIF build info")?;
+
+        let datetime: DateTime<Utc> = SystemTime::now().into();
+        // Identify the exact revision of the tool as semver build metadata, when known.
+        let version = match env!("KRUN_EIF_CTL_GIT_SHA") {
+            "" => env!("CARGO_PKG_VERSION").to_string(),
+            sha => format!("{}+{sha}", env!("CARGO_PKG_VERSION")),
+        };
+
+        Ok(EifIdentityInfo {
+            img_name: "krun-awsnitro-eif".to_string(),
+            img_version: "n/a".to_string(),
+            build_info: EifBuildInfo {
+                build_time: format!("{}", datetime),
+                build_tool: "krun-awsnitro-eif-ctl".to_string(),
+                build_tool_version: version,
+                img_os: "n/a".to_string(),
+                img_kernel: kernel_name,
+            },
+            docker_info: Value::Null,
+            custom_info,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::os::unix::fs::PermissionsExt;
+
+        /// Empty scratch directory unique to this test run.
+        fn scratch(name: &str) -> PathBuf {
+            let dir = env::temp_dir().join(format!("krun-awsnitro-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        fn initrd(path: PathBuf, modules: Vec<PathBuf>) -> Initrd {
+            Initrd {
+                path,
+                init: None,
+                modules,
+                max_module_size: None,
+                extra_files: Vec::new(),
+                sort_entries: SortEntries::Name,
+                entry_prefix: None,
+                prune_empty_dirs: false,
+                modules_kernel_version: None,
+                module_read_retries: 0,
+                warnings: Vec::new(),
+            }
+        }
+
+        #[test]
+        fn entry_modes_do_not_depend_on_source_permissions() {
+            let dir = scratch("entry-modes");
+            let module = dir.join("a.ko");
+            fs::write(&module, b"module").unwrap();
+
+            let mut archives = Vec::new();
+            for mode in [0o600, 0o644] {
+                fs::set_permissions(&module, fs::Permissions::from_mode(mode)).unwrap();
+                let path = dir.join(format!("initrd-{mode:o}.img"));
+                initrd(path.clone(), vec![module.clone()]).build().unwrap();
+                archives.push(fs::read(path).unwrap());
+            }
+            assert_eq!(archives[0], archives[1]);
+
+            let mut modes = Vec::new();
+            let mut rest = archives[0].as_slice();
+            loop {
+                let reader = cpio::newc::Reader::new(rest).unwrap();
+                if reader.entry().is_trailer() {
+                    break;
+                }
+                modes.push((reader.entry().name().to_string(), reader.entry().mode()));
+                rest = reader.finish().unwrap();
+            }
+            assert_eq!(
+                modes,
+                [
+                    ("krun_linux_mods".to_string(), DIR_MODE),
+                    ("krun_linux_mods/a.ko".to_string(), FILE_MODE),
+                ]
+            );
+
+            fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn identity_json_is_independent_of_key_order_and_whitespace() {
+            let args = BuildArgs::try_parse_from([
+                "build",
+                "--arch",
+                "x86_64",
+                "--kernel",
+                "bzImage",
+                "--kernel-modules",
+                "modules.json",
+            ])
+            .unwrap();
+
+            let serialized = |json: &str| {
+                let mut identity = build_info(&args, serde_json::from_str(json).unwrap()).unwrap();
+                // The build time differs between calls, and is not under test.
+                identity.build_info.build_time = String::new();
+                serde_json::to_vec(&identity).unwrap()
+            };
+
+            assert_eq!(
+                serialized(r#"{"b": {"y": 1, "x": [2, 3]}, "a": "1"}"#),
+                serialized("{\"a\":\"1\",\n  \"b\":{\"x\":[2,3],\"y\":1}}"),
+            );
+        }
+
+        #[test]
+        fn measurements_from_sources_match_the_eif_builder_and_written_eif() {
+            use openssl::{
+                asn1::Asn1Time,
+                ec::{EcGroup, EcKey},
+                hash::MessageDigest,
+                nid::Nid,
+                pkey::PKey,
+                x509::X509NameBuilder,
+            };
+
+            let dir = scratch("measurements");
+            let (kernel, module) = (dir.join("bzImage"), dir.join("a.ko"));
+            fs::write(
+                &kernel,
+                (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>(),
+            )
+            .unwrap();
+            fs::write(&module, b"module").unwrap();
+            let bootstrap = dir.join("bootstrap-initrd.img");
+            initrd(bootstrap.clone(), vec![module]).build().unwrap();
+            let app = dir.join("app.sqfs");
+            fs::write(&app, b"hsqs application").unwrap();
+
+            let key = PKey::from_ec_key(
+                EcKey::generate(&EcGroup::from_curve_name(Nid::SECP384R1).unwrap()).unwrap(),
+            )
+            .unwrap();
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_text("CN", "test").unwrap();
+            let name = name.build();
+            let mut cert = X509::builder().unwrap();
+            cert.set_version(2).unwrap();
+            cert.set_subject_name(&name).unwrap();
+            cert.set_issuer_name(&name).unwrap();
+            cert.set_pubkey(&key).unwrap();
+            cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                .unwrap();
+            cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+                .unwrap();
+            cert.sign(&key, MessageDigest::sha384()).unwrap();
+            let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+            fs::write(&cert_path, cert.build().to_pem().unwrap()).unwrap();
+            fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+            let args = BuildArgs::try_parse_from([
+                "build".as_ref(),
+                "--arch".as_ref(),
+                "x86_64".as_ref(),
+                "--kernel".as_ref(),
+                kernel.as_os_str(),
+                "--kernel-modules".as_ref(),
+                "modules.json".as_ref(),
+                "--signing-certificate".as_ref(),
+                cert_path.as_os_str(),
+                "--signing-key".as_ref(),
+                key_path.as_os_str(),
+            ])
+            .unwrap();
+            let cmdline = "console=ttyS0 reboot=k panic=1".to_string();
+            let identity = build_info(&args, Value::Null).unwrap();
+
+            let sources = measure_from_sources(&args, &cmdline, &[&bootstrap, &app]).unwrap();
+
+            let builder = || {
+                let sign_info =
+                    SignEnclaveInfo::new(cert_path.to_str().unwrap(), key_path.to_str().unwrap())
+                        .unwrap();
+                let mut build = EifBuilder::new(
+                    &kernel,
+                    cmdline.clone(),
+                    Some(sign_info),
+                    Sha384::new(),
+                    0,
+                    identity.clone(),
+                );
+                build.add_ramdisk(&bootstrap);
+                build.add_ramdisk(&app);
+                build
+            };
+            assert_eq!(sources, builder_measurements(&mut builder()).unwrap());
+
+            let path = dir.join("image.eif");
+            builder().write_to(&mut File::create(&path).unwrap());
+            let written =
+                crate::eif::measurements(&crate::eif::Eif::read(&path).unwrap(), &crate::eif::PCRS)
+                    .unwrap();
+            assert_eq!(sources, written);
+            assert!(
+                ["PCR0", "PCR1", "PCR2", "PCR8"]
+                    .iter()
+                    .all(|pcr| sources.contains_key(*pcr))
+            );
+
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
+
+/// Subcommand to build only the krun-awsnitro initrd, without wrapping it in an EIF.
+pub mod initrd {
+    use super::*;
+    use anyhow::Context;
+    use sha2::{Digest, Sha384};
+    use std::{fs::File, io, path::PathBuf};
+
+    /// Arguments to configure the initrd built.
+    #[derive(Parser)]
+    pub(super) struct InitrdArgs {
+        /// Path to write the krun-awsnitro initrd.
+        #[arg(short, long, default_value = "/etc/krun-awsnitro/bootstrap-initrd.img")]
+        path: PathBuf,
+        #[command(flatten)]
+        options: InitrdOptions,
+    }
+
+    pub(super) fn initrd(args: InitrdArgs) -> Result<()> {
+        let mut initrd =
+            Initrd::new(&args.options, args.path.clone()).context("unable to build initrd")?;
+        for warning in &initrd.warnings {
+            eprintln!("WARNING: {warning}");
+        }
+        initrd.build().context("unable to build initrd")?;
+
+        let mut file = File::open(&args.path).context(format!("unable to open {:?}", args.path))?;
+        let mut hasher = Sha384::new();
+        let size =
+            io::copy(&mut file, &mut hasher).context(format!("unable to hash {:?}", args.path))?;
+
+        println!("Path: {}", args.path.display());
+        println!("Size: {size}");
+        println!("SHA384: {:x}", hasher.finalize());
+
+        Ok(())
+    }
+}
+
+/// Subcommand to extract the JSON metadata embedded in an existing EIF image.
+pub mod metadata {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use aws_nitro_enclaves_image_format::utils::eif_reader::EifReader;
+    use clap::ValueEnum;
+    use serde_json::Value;
+    use std::path::PathBuf;
+
+    #[derive(Clone, Debug, ValueEnum)]
+    pub enum Field {
+        #[clap(name = "custom_info")]
+        CustomInfo,
+        #[clap(name = "docker_info")]
+        DockerInfo,
+        #[clap(name = "identity")]
+        Identity,
+    }
+
+    /// Arguments to select the metadata extracted from an EIF file.
+    #[derive(Parser)]
+    pub(super) struct MetadataArgs {
+        /// Path of the EIF image to read.
+        #[arg(short, long, default_value = "/etc/krun-awsnitro/krun-awsnitro.eif")]
+        path: PathBuf,
+        /// Metadata field to extract.
+        #[arg(long, default_value = "identity")]
+        field: Field,
+        /// Print the JSON unformatted (suitable for piping to other tools).
+        #[arg(long)]
+        raw: bool,
+    }
+
+    pub(super) fn metadata(args: MetadataArgs) -> Result<()> {
+        let reader = EifReader::from_eif(format!("{}", args.path.display()))
+            .map_err(|e| anyhow!(e))
+            .with_context(|| format!("unable to read EIF {}", args.path.display()))?;
+
+        let identity = reader
+            .get_metadata()
+            .with_context(|| format!("{} contains no metadata section", args.path.display()))?;
+
+        let value = match args.field {
+            Field::CustomInfo => identity.custom_info,
+            Field::DockerInfo => identity.docker_info,
+            Field::Identity => serde_json::to_value(&identity)
+                .context("unable to serialize EIF identity info to JSON")?,
+        };
+
+        println!("{}", to_json_string(&value, args.raw)?);
+
+        Ok(())
+    }
+
+    fn to_json_string(value: &Value, raw: bool) -> Result<String> {
+        let json = match raw {
+            true => serde_json::to_string(value),
+            false => serde_json::to_string_pretty(value),
+        };
+
+        json.context("unable to serialize EIF metadata to JSON")
+    }
+}
+
+/// Subcommand to lint a JSON-serialized kernel modules list without building an EIF.
+pub mod validate_modules {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use serde_json::Value;
+    use std::path::{Component, Path, PathBuf};
+
+    /// Arguments to configure the kernel modules list validation.
+    #[derive(Parser)]
+    pub(super) struct ValidateModulesArgs {
+        /// JSON-serialized kernel modules list to validate.
+        file: PathBuf,
+    }
+
+    pub(super) fn validate_modules(args: ValidateModulesArgs) -> Result<()> {
+        // Compressed lists are accepted, as they are by the build.
+        let contents = read_decompressed(&args.file)?;
+
+        let json: Value = serde_json::from_slice(&contents)
+            .context(format!("unable to deserialize {:?} to JSON", args.file))?;
+
+        let problems = problems(&json);
+        if !problems.is_empty() {
+            for problem in problems.iter() {
+                eprintln!("{}: {problem}", args.file.display());
+            }
+
+            return Err(anyhow!(
+                "{} problem(s) found in {}",
+                problems.len(),
+                args.file.display()
+            ));
+        }
+
+        println!("{}: OK", args.file.display());
+
+        Ok(())
+    }
+
+    /// Collect every problem found in the modules list, rather than stopping at the first.
+    fn problems(json: &Value) -> Vec<String> {
+        let Value::Array(entries) = json else {
+            return vec!["expected a JSON array of kernel module paths".to_string()];
+        };
+
+        let mut problems = Vec::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            let Value::String(path) = entry else {
+                problems.push(format!("entry {idx}: expected a string, found {entry}"));
+                continue;
+            };
+
+            if path.is_empty() {
+                problems.push(format!("entry {idx}: path is empty"));
+                continue;
+            }
+
+            if Path::new(path)
+                .components()
+                .any(|c| matches!(c, Component::ParentDir))
+            {
+                problems.push(format!("entry {idx}: path {path:?} contains \"..\""));
+            }
+
+            if Path::new(path).file_name().is_none() {
+                problems.push(format!("entry {idx}: path {path:?} has no file name"));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Subcommand to report what contributes to the size of an existing EIF image.
+pub mod analyze {
+    use super::*;
+    use anyhow::Context;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// Arguments to configure the EIF size analysis.
+    #[derive(Parser)]
+    pub(super) struct AnalyzeArgs {
+        /// Path of the EIF image to analyze.
+        #[arg(short, long, default_value = "/etc/krun-awsnitro/krun-awsnitro.eif")]
+        path: PathBuf,
+        /// Number of largest ramdisk entries to report.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        /// Print the analysis as JSON.
+        #[arg(long)]
+        json: bool,
+    }
+
+    pub(super) fn analyze(args: AnalyzeArgs) -> Result<()> {
+        let eif = eif::Eif::read(&args.path)?;
+
+        let mut entries = Vec::new();
+        for (idx, ramdisk) in eif.ramdisks().enumerate() {
+            let cpio = eif::cpio_entries(&ramdisk.data)
+                .with_context(|| format!("unable to parse ramdisk {idx} as a CPIO archive"))?;
+            entries.extend(cpio.into_iter().map(|entry| (idx, entry)));
+        }
+        entries.sort_by(|a, b| b.1.size.cmp(&a.1.size).then(a.1.name.cmp(&b.1.name)));
+        entries.truncate(args.top);
+
+        if args.json {
+            let sections: Vec<_> = eif
+                .sections
+                .iter()
+                .map(|s| json!({ "type": eif::section_name(s), "size": s.data.len() }))
+                .collect();
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(idx, e)| json!({ "ramdisk": idx, "name": e.name, "size": e.size }))
+                .collect();
+
+            let json = json!({ "sections": sections, "entries": entries });
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json).context("unable to serialize analysis")?
+            );
+
+            return Ok(());
+        }
+
+        println!("Sections:");
+        for section in eif.sections.iter() {
+            println!(
+                "\t{:<12}{:>12} bytes",
+                eif::section_name(section),
+                section.data.len()
+            );
+        }
+
+        println!("Largest ramdisk entries:");
+        for (idx, entry) in entries.iter() {
+            println!("\t{:>12} bytes\tramdisk {idx}: {}", entry.size, entry.name);
+        }
+
+        Ok(())
+    }
+}
+
+/// Subcommand to print the JSON Schema of the files consumed by the tool.
+pub mod schema {
+    use super::*;
+    use anyhow::Context;
+    use clap::ValueEnum;
+
+    #[derive(Clone, Debug, ValueEnum)]
+    pub enum Format {
+        /// The kernel modules list passed to --kernel-modules.
+        #[clap(name = "modules")]
+        Modules,
+    }
+
+    /// Arguments to select the JSON Schema printed.
+    #[derive(Parser)]
+    pub(super) struct SchemaArgs {
+        /// File format to print the JSON Schema of.
+        format: Format,
+    }
+
+    pub(super) fn schema(args: SchemaArgs) -> Result<()> {
+        let schema = match args.format {
+            Format::Modules => schemars::schema_for!(build::Modules),
+        };
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema).context("unable to serialize JSON Schema")?
+        );
+
+        Ok(())
+    }
+}
+
+/// Subcommand to verify the measurements of an EIF against an attestation policy.
+pub mod verify {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use std::{
+        collections::BTreeMap,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    /// Attestation policy, mapping each environment name to the PCR sets accepted there. A PCR
+    /// set (e.g. `{"PCR0": "...", "PCR1": "..."}`) matches if every PCR it lists matches.
+    pub(super) type Policy = BTreeMap<String, Vec<BTreeMap<String, String>>>;
+
+    /// Arguments to configure the EIF policy verification.
+    #[derive(Parser)]
+    pub(super) struct VerifyArgs {
+        /// Path of the EIF image to verify.
+        #[arg(short, long, default_value = "/etc/krun-awsnitro/krun-awsnitro.eif")]
+        path: PathBuf,
+        /// JSON policy file listing the accepted PCR sets of each environment.
+        #[arg(long)]
+        policy: PathBuf,
+        /// Environment the EIF must be accepted in.
+        #[arg(long)]
+        environment: String,
+    }
+
+    pub(super) fn verify(args: VerifyArgs) -> Result<()> {
+        let policy = read_policy(&args.policy)?;
+        if !policy.contains_key(&args.environment) {
+            return Err(anyhow!(
+                "environment {:?} is not defined in {}",
+                args.environment,
+                args.policy.display()
+            ));
+        }
+
+        let measurements = measurements(&args.path, &policy)?;
+
+        let valid: Vec<&String> = policy
+            .iter()
+            .filter(|(_, sets)| {
+                sets.iter()
+                    .any(|set| mismatches(set, &measurements).is_empty())
+            })
+            .map(|(env, _)| env)
+            .collect();
+
+        println!(
+            "Valid for: {}",
+            match valid.is_empty() {
+                true => "<none>".to_string(),
+                false => valid
+                    .iter()
+                    .map(|e| e.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            }
+        );
+
+        if !valid.contains(&&args.environment) {
+            println!("{}: FAIL", args.environment);
+            return Err(anyhow!(
+                "{} is not accepted by the {:?} environment policy",
+                args.path.display(),
+                args.environment
+            ));
+        }
+
+        // PCRs such as PCR3 (IAM role) and PCR4 (instance ID) are only known at runtime, so a
+        // set is accepted on its verifiable PCRs, reporting the rest as not verified.
+        let unverified = policy[&args.environment]
+            .iter()
+            .find(|set| mismatches(set, &measurements).is_empty())
+            .map(unverifiable)
+            .unwrap_or_default();
+        match unverified.is_empty() {
+            true => println!("{}: PASS", args.environment),
+            false => println!(
+                "{}: PASS ({} not verifiable from the image)",
+                args.environment,
+                unverified
+                    .iter()
+                    .map(|k| k.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn read_policy(path: &Path) -> Result<Policy> {
+        let json_str =
+            fs::read_to_string(path).context(format!("unable to read {:?} to string", path))?;
+
+        serde_json::from_str(&json_str)
+            .context(format!("unable to deserialize {:?} to a policy", path))
+    }
+
+    /// Compute the measurements of the EIF at `path` needed to evaluate `policy`.
+    pub(super) fn measurements(path: &Path, policy: &Policy) -> Result<BTreeMap<String, String>> {
+        eif::measurements(&eif::Eif::read(path)?, &pcrs(policy))
+    }
+
+    /// Index of the PCR named by a policy key, if it can be computed from an EIF.
+    fn pcr_index(key: &str) -> Option<u8> {
+        key.strip_prefix("PCR")?
+            .parse()
+            .ok()
+            .filter(|pcr| eif::PCRS.contains(pcr))
+    }
+
+    /// PCRs listed by any of the PCR sets of the policy that can be computed from an EIF.
+    pub(super) fn pcrs(policy: &Policy) -> Vec<u8> {
+        let mut pcrs: Vec<u8> = policy
+            .values()
+            .flatten()
+            .flat_map(|set| set.keys())
+            .filter_map(|key| pcr_index(key))
+            .collect();
+        pcrs.sort();
+        pcrs.dedup();
+
+        pcrs
+    }
+
+    /// Keys of the PCR set that can be computed from the EIF, but do not match the measurements.
+    pub(super) fn mismatches<'a>(
+        set: &'a BTreeMap<String, String>,
+        measurements: &BTreeMap<String, String>,
+    ) -> Vec<&'a String> {
+        set.iter()
+            .filter(|(key, value)| {
+                pcr_index(key).is_some()
+                    && measurements
+                        .get(*key)
+                        .is_none_or(|m| !m.eq_ignore_ascii_case(value))
+            })
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Keys of the PCR set that cannot be computed from an EIF (e.g. PCR3 and PCR4), and so
+    /// are neither matched nor mismatched.
+    pub(super) fn unverifiable(set: &BTreeMap<String, String>) -> Vec<&String> {
+        set.keys().filter(|key| pcr_index(key).is_none()).collect()
+    }
+}
+
+/// Subcommand to describe the header, sections, metadata and measurements of an EIF image.
+pub mod inspect {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use aws_nitro_enclaves_image_format::defs::{EIF_HDR_ARCH_ARM64, EifSectionType};
+    use serde_json::{Value, json};
+    use std::path::PathBuf;
+
+    /// Arguments to configure the EIF inspection.
+    #[derive(Parser)]
+    pub(super) struct InspectArgs {
+        /// Path of the EIF image to inspect.
+        #[arg(short, long, default_value = "/etc/krun-awsnitro/krun-awsnitro.eif")]
+        path: PathBuf,
+        /// Print the complete description of the EIF as a single JSON document.
+        #[arg(long)]
+        json: bool,
+        /// Comma-separated PCRs to compute (PCR8 is only reported for signed EIFs).
+        #[arg(long, value_delimiter = ',', default_values_t = eif::PCRS)]
+        pcrs: Vec<u8>,
+        /// Check that the architecture in the EIF header matches that of the embedded kernel and
+        /// kernel modules.
+        #[arg(long)]
+        check_arch: bool,
+        /// JSON policy file (as used by verify) to list the matching and mismatching PCRs of
+        /// the EIF against, for every environment.
+        #[arg(long)]
+        diff_policy: Option<PathBuf>,
+    }
+
+    pub(super) fn inspect(args: InspectArgs) -> Result<()> {
+        let eif = eif::Eif::read(&args.path)?;
+
+        let mut description = describe(&eif, &args.pcrs)?;
+        if args.check_arch {
+            description["arch"] = check_arch(&eif)?;
+        }
+        if let Some(policy) = &args.diff_policy {
+            description["policy"] = diff_policy(&eif, &read_policy(policy)?)?;
+        }
+
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&description)
+                    .context("unable to serialize EIF description")?
+            );
+
+            return Ok(());
+        }
+
+        let header = &description["header"];
+        println!("Path: {}", args.path.display());
+        println!("Version: {}", header["version"]);
+        println!("Flags: {:#06x}", header["flags"].as_u64().unwrap_or(0));
+        if let Some(arch) = description["arch"].as_object() {
+            println!(
+                "Architecture: {} (kernel and {} modules match)",
+                arch["arch"].as_str().unwrap_or_default(),
+                arch["modules"]
+            );
+        }
+        println!(
+            "CRC32: {:#010x} (valid: {})",
+            header["crc32"].as_u64().unwrap_or(0),
+            header["crc_valid"]
+        );
+        println!("Sections:");
+        for section in description["sections"].as_array().into_iter().flatten() {
+            println!(
+                "\t{:<12}offset {:>12}\tsize {:>12}",
+                section["type"].as_str().unwrap_or_default(),
+                section["offset"],
+                section["size"]
+            );
+        }
+        println!("Measurements:");
+        for (key, value) in description["measurements"]
+            .as_object()
+            .into_iter()
+            .flatten()
+        {
+            println!("\t{key}: {}", value.as_str().unwrap_or_default());
+        }
+        println!(
+            "Identity: {}",
+            serde_json::to_string_pretty(&description["identity"])
+                .context("unable to serialize EIF identity info")?
+        );
+        if let Some(policy) = description["policy"].as_object() {
+            let list = |value: &Value| match value.as_array().filter(|keys| !keys.is_empty()) {
+                Some(keys) => keys
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => "<none>".to_string(),
+            };
+
+            println!("Policy:");
+            for (environment, standing) in policy {
+                println!(
+                    "\t{environment}: {}",
+                    match standing["valid"].as_bool() {
+                        Some(true) => "PASS",
+                        _ => "FAIL",
+                    }
+                );
+                for (i, set) in standing["sets"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                {
+                    println!(
+                        "\t\tset {i}: matching {}; mismatching {}; unverifiable {}",
+                        list(&set["matching"]),
+                        list(&set["mismatching"]),
+                        list(&set["unverifiable"])
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// For every environment of the policy, list which PCRs of each of its accepted PCR sets
+    /// the EIF matches, which it does not, and which cannot be computed from an EIF.
+    fn diff_policy(eif: &eif::Eif, policy: &Policy) -> Result<Value> {
+        let measurements = eif::measurements(eif, &pcrs(policy))?;
+
+        let environments = policy
+            .iter()
+            .map(|(environment, sets)| {
+                let sets: Vec<Value> = sets
+                    .iter()
+                    .map(|set| {
+                        let mismatching = mismatches(set, &measurements);
+                        let unverifiable = unverifiable(set);
+                        let matching: Vec<&String> = set
+                            .keys()
+                            .filter(|key| !mismatching.contains(key) && !unverifiable.contains(key))
+                            .collect();
+
+                        json!({
+                            "matching": matching,
+                            "mismatching": mismatching,
+                            "unverifiable": unverifiable,
+                        })
+                    })
+                    .collect();
+                let valid = sets
+                    .iter()
+                    .any(|set| set["mismatching"].as_array().is_some_and(Vec::is_empty));
+
+                (environment.clone(), json!({ "valid": valid, "sets": sets }))
+            })
+            .collect::<serde_json::Map<String, Value>>();
+
+        Ok(Value::Object(environments))
+    }
+
+    /// Verify that the kernel and every (uncompressed) kernel module in the ramdisks of the EIF
+    /// are built for the architecture recorded in its header flags.
+    fn check_arch(eif: &eif::Eif) -> Result<Value> {
+        let header = match eif.header.flags & EIF_HDR_ARCH_ARM64 {
+            0 => "x86_64",
+            _ => "aarch64",
+        };
+
+        let kernel = eif
+            .sections
+            .iter()
+            .find(|s| s.header.section_type == EifSectionType::EifSectionKernel)
+            .context("EIF has no kernel section")?;
+        match eif::arch_of(&kernel.data) {
+            Some(arch) if arch == header => (),
+            Some(arch) => {
+                return Err(anyhow!(
+                    "EIF header flags claim {header}, but the kernel is {arch}"
+                ));
+            }
+            None => return Err(anyhow!("unable to detect the architecture of the kernel")),
+        }
+
+        let mut modules = 0;
+        for ramdisk in eif.ramdisks() {
+            // Ramdisks that aren't CPIO archives (e.g. squashfs images) contain no modules.
+            let Ok(entries) = eif::cpio_entries(&ramdisk.data) else {
+                continue;
+            };
+
+            for entry in entries.iter().filter(|e| e.name.ends_with(".ko")) {
+                match eif::arch_of(entry.data) {
+                    Some(arch) if arch == header => modules += 1,
+                    Some(arch) => {
+                        return Err(anyhow!(
+                            "EIF header flags claim {header}, but module {} is {arch}",
+                            entry.name
+                        ));
+                    }
+                    None => return Err(anyhow!("module {} is not an ELF object", entry.name)),
+                }
+            }
+        }
+
+        Ok(json!({ "arch": header, "modules": modules }))
+    }
+
+    /// Describe the complete parsed contents of an EIF.
+    fn describe(eif: &eif::Eif, pcrs: &[u8]) -> Result<Value> {
+        let measurements = eif::measurements(eif, pcrs)?;
+
+        let sections: Vec<Value> = eif
+            .sections
+            .iter()
+            .map(|s| {
+                json!({
+                    "type": eif::section_name(s),
+                    "flags": s.header.flags,
+                    "offset": s.offset,
+                    "size": s.header.section_size,
+                })
+            })
+            .collect();
+
+        Ok(json!({
+            "header": {
+                "magic": String::from_utf8_lossy(&eif.header.magic),
+                "version": eif.header.version,
+                "flags": eif.header.flags,
+                "default_mem": eif.header.default_mem,
+                "default_cpus": eif.header.default_cpus,
+                "num_sections": eif.header.num_sections,
+                "crc32": eif.header.eif_crc32,
+                "crc_valid": eif.crc_valid,
+            },
+            "sections": sections,
+            "identity": eif.identity()?,
+            "measurements": measurements,
+        }))
+    }
+}
+
+/// Subcommand to locate the differences between two EIF images expected to be identical.
+pub mod diff {
+    use super::*;
+    use anyhow::anyhow;
+    use aws_nitro_enclaves_image_format::defs::EifSectionType;
+    use std::path::PathBuf;
+
+    /// Arguments to configure the EIF comparison.
+    #[derive(Parser)]
+    pub(super) struct DiffArgs {
+        /// Path of the first EIF image.
+        a: PathBuf,
+        /// Path of the second EIF image.
+        b: PathBuf,
+    }
+
+    pub(super) fn diff(args: DiffArgs) -> Result<()> {
+        let a = eif::Eif::read(&args.a)?;
+        let b = eif::Eif::read(&args.b)?;
+
+        let mut differences = 0;
+        if a.sections.len() != b.sections.len() {
+            println!(
+                "section count differs: {} has {}, {} has {}",
+                args.a.display(),
+                a.sections.len(),
+                args.b.display(),
+                b.sections.len()
+            );
+            differences += 1;
+        }
+
+        for (idx, (x, y)) in a.sections.iter().zip(b.sections.iter()).enumerate() {
+            if x.header.section_type != y.header.section_type {
+                println!(
+                    "section {idx}: type differs ({} vs {})",
+                    eif::section_name(x),
+                    eif::section_name(y)
+                );
+                differences += 1;
+                continue;
+            }
+
+            let Some(offset) = first_difference(&x.data, &y.data) else {
+                continue;
+            };
+            differences += 1;
+
+            let name = eif::section_name(x);
+            let sizes = match x.data.len() == y.data.len() {
+                true => String::new(),
+                false => format!(" (sizes {} vs {})", x.data.len(), y.data.len()),
+            };
+            println!("section {idx} ({name}): differs at byte {offset}{sizes}");
+
+            if x.header.section_type == EifSectionType::EifSectionRamdisk
+                && let Some(entry) = differing_entry(&x.data, &y.data, offset)
+            {
+                println!("\t{entry}");
+            }
+        }
+
+        match differences {
+            0 if a.header.flags == b.header.flags => {
+                println!("EIFs are identical");
+                Ok(())
+            }
+            0 => Err(anyhow!(
+                "EIF header flags differ ({:#06x} vs {:#06x})",
+                a.header.flags,
+                b.header.flags
+            )),
+            n => Err(anyhow!("{n} difference(s) found between the EIFs")),
+        }
+    }
+
+    /// Offset of the first byte that differs between `a` and `b`, if any.
+    fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+        match a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+            Some(offset) => Some(offset),
+            None if a.len() != b.len() => Some(a.len().min(b.len())),
+            None => None,
+        }
+    }
+
+    /// Describe the CPIO entry of ramdisk `a` containing its first difference from `b` at
+    /// `offset`, if both are CPIO archives.
+    fn differing_entry(a: &[u8], b: &[u8], offset: usize) -> Option<String> {
+        let a = eif::cpio_entries(a).ok()?;
+        let b = eif::cpio_entries(b).ok()?;
+
+        let find = |entries: &[eif::CpioEntry]| {
+            let idx = entries.iter().rposition(|e| e.offset <= offset)?;
+            Some((idx, entries[idx].name.clone()))
+        };
+
+        match (find(&a), find(&b)) {
+            (Some((idx, x)), Some((_, y))) if x == y => {
+                let at = offset - a[idx].offset;
+                let header = (110 + x.len() + 1).next_multiple_of(4);
+
+                Some(match at.checked_sub(header) {
+                    Some(at) => format!("in CPIO entry {idx} {x:?}, at byte {at} of its contents"),
+                    None => format!("in CPIO entry {idx} {x:?}, in its header"),
+                })
+            }
+            (Some((idx, x)), Some((_, y))) => Some(format!("at CPIO entry {idx}: {x:?} vs {y:?}")),
+            // The difference is in the trailer.
+            _ => None,
+        }
+    }
+}
+
+/// Helpers to read the sections of an existing EIF image.
+mod eif {
+    use anyhow::{Context, Result, anyhow};
+    use aws_nitro_enclaves_image_format::defs::{
+        EifHeader, EifIdentityInfo, EifSectionHeader, EifSectionType, MAX_NUM_SECTIONS,
+        PcrSignature, eif_hasher::EifHasher,
+    };
+    use cpio::newc::Reader;
+    use crc::{CRC_32_ISO_HDLC, Crc};
+    use openssl::x509::X509;
+    use sha2::{Digest, Sha384};
+    use std::{collections::BTreeMap, fs, io::Write, mem::size_of, path::Path};
+
+    pub struct Section {
+        pub header: EifSectionHeader,
+        /// Offset of the section data within the EIF.
+        pub offset: u64,
+        pub data: Vec<u8>,
+    }
+
+    pub struct Eif {
+        pub header: EifHeader,
+        pub sections: Vec<Section>,
+        /// Whether the CRC recorded in the header matches the contents of the EIF.
+        pub crc_valid: bool,
+    }
+
+    impl Eif {
+        pub fn read(path: &Path) -> Result<Self> {
+            let bytes =
+                fs::read(path).with_context(|| format!("unable to read EIF {}", path.display()))?;
+
+            let header = EifHeader::from_be_bytes(
+                bytes
+                    .get(..EifHeader::size())
+                    .context("EIF is too small to contain a header")?,
+            )
+            .map_err(|e| anyhow!(e))
+            .context("unable to parse EIF header")?;
+
+            let num_sections = header.num_sections as usize;
+            if num_sections > MAX_NUM_SECTIONS {
+                return Err(anyhow!(
+                    "EIF header lists {num_sections} sections, more than the maximum of {MAX_NUM_SECTIONS}"
+                ));
+            }
+
+            let mut sections = Vec::new();
+            for idx in 0..num_sections {
+                let start = usize::try_from(header.section_offsets[idx])
+                    .ok()
+                    .with_context(|| format!("EIF section {idx} offset is out of bounds"))?;
+                let data_start = start
+                    .checked_add(EifSectionHeader::size())
+                    .with_context(|| format!("EIF section {idx} header is out of bounds"))?;
+
+                let section_header = EifSectionHeader::from_be_bytes(
+                    bytes
+                        .get(start..data_start)
+                        .with_context(|| format!("EIF section {idx} header is out of bounds"))?,
+                )
+                .map_err(|e| anyhow!(e))
+                .with_context(|| format!("unable to parse EIF section {idx} header"))?;
+
+                let data_end = usize::try_from(section_header.section_size)
+                    .ok()
+                    .and_then(|size| data_start.checked_add(size))
+                    .with_context(|| format!("EIF section {idx} data is out of bounds"))?;
+                let data = bytes
+                    .get(data_start..data_end)
+                    .with_context(|| format!("EIF section {idx} data is out of bounds"))?
+                    .to_vec();
+
+                sections.push(Section {
+                    header: section_header,
+                    offset: data_start as u64,
+                    data,
+                });
+            }
+
+            // The CRC covers everything but the CRC field itself, the last field of the header.
+            let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+            let mut digest = crc.digest();
+            digest.update(&bytes[..EifHeader::size() - size_of::<u32>()]);
+            digest.update(&bytes[EifHeader::size()..]);
+            let crc_valid = digest.finalize() == header.eif_crc32;
+
+            Ok(Self {
+                header,
+                sections,
+                crc_valid,
+            })
+        }
+
+        /// Identity info recorded in the metadata section, if the EIF has one.
+        pub fn identity(&self) -> Result<Option<EifIdentityInfo>> {
+            self.sections
+                .iter()
+                .find(|s| s.header.section_type == EifSectionType::EifSectionMetadata)
+                .map(|s| {
+                    serde_json::from_slice(&s.data).context("unable to deserialize EIF metadata")
+                })
+                .transpose()
+        }
+
+        pub fn ramdisks(&self) -> impl Iterator<Item = &Section> {
+            self.sections
+                .iter()
+                .filter(|s| s.header.section_type == EifSectionType::EifSectionRamdisk)
+        }
+    }
+
+    /// ELF e_machine values of x86_64 and aarch64 kernels and modules.
+    const ELF_MACHINE_X86_64: u16 = 62;
+    const ELF_MACHINE_AARCH64: u16 = 183;
+
+    /// PCRs that can be computed from an EIF.
+    pub const PCRS: [u8; 4] = [0, 1, 2, 8];
+
+    /// Compute the requested PCRs of an EIF, as reported by nitro-cli. Sections are only hashed
+    /// for the PCRs that cover them, so omitting PCR0 skips hashing the whole image. PCR8 is only
+    /// reported for signed EIFs.
+    pub fn measurements(eif: &Eif, pcrs: &[u8]) -> Result<BTreeMap<String, String>> {
+        if let Some(pcr) = pcrs.iter().find(|p| !PCRS.contains(p)) {
+            return Err(anyhow!(
+                "PCR{pcr} cannot be computed from an EIF (supported: {PCRS:?})"
+            ));
+        }
+
+        let boot = eif.sections.iter().filter(|s| {
+            matches!(
+                s.header.section_type,
+                EifSectionType::EifSectionKernel | EifSectionType::EifSectionCmdline
+            )
+        });
+        let ramdisks: Vec<&Section> = eif.ramdisks().collect();
+
+        let mut measurements = BTreeMap::new();
+        measurements.insert("HashAlgorithm".to_string(), format!("{:?}", Sha384::new()));
+
+        for pcr in pcrs {
+            let covered: Vec<&Section> = match pcr {
+                0 => boot.clone().chain(ramdisks.iter().copied()).collect(),
+                1 => boot.clone().chain(ramdisks.first().copied()).collect(),
+                2 => ramdisks.iter().skip(1).copied().collect(),
+                _ => continue,
+            };
+
+            let data: Vec<&[u8]> = covered.iter().map(|s| &s.data[..]).collect();
+            measurements.insert(format!("PCR{pcr}"), pcr_value(&data)?);
+        }
+
+        if pcrs.contains(&8)
+            && let Some(signature) = eif
+                .sections
+                .iter()
+                .find(|s| s.header.section_type == EifSectionType::EifSectionSignature)
+        {
+            let signatures: Vec<PcrSignature> = serde_cbor::from_slice(&signature.data)
+                .context("unable to deserialize EIF signature section")?;
+            let cert = X509::from_pem(
+                &signatures
+                    .first()
+                    .context("EIF signature section contains no signatures")?
+                    .signing_certificate,
+            )
+            .context("unable to parse EIF signing certificate")?;
+            let der = cert
+                .to_der()
+                .context("unable to serialize EIF signing certificate to DER")?;
+
+            measurements.insert("PCR8".to_string(), pcr_value(&[&der])?);
+        }
+
+        Ok(measurements)
+    }
+
+    /// Value of a PCR extended (once) with the hash of `data`.
+    fn pcr_value(data: &[&[u8]]) -> Result<String> {
+        let mut hasher = EifHasher::new_without_cache(Sha384::new()).map_err(|e| anyhow!(e))?;
+        for chunk in data {
+            hasher
+                .write_all(chunk)
+                .context("unable to hash EIF section")?;
+        }
+
+        let digest = hasher
+            .tpm_extend_finalize_reset()
+            .context("unable to finalize PCR hash")?;
+
+        Ok(digest.iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    pub fn section_name(section: &Section) -> &'static str {
+        match section.header.section_type {
+            EifSectionType::EifSectionInvalid => "invalid",
+            EifSectionType::EifSectionKernel => "kernel",
+            EifSectionType::EifSectionCmdline => "cmdline",
+            EifSectionType::EifSectionRamdisk => "ramdisk",
+            EifSectionType::EifSectionSignature => "signature",
+            EifSectionType::EifSectionMetadata => "metadata",
+        }
+    }
+
+    /// Architecture of a kernel image (x86 bzImage, arm64 Image or ELF vmlinux) or ELF object
+    /// such as a kernel module, if it can be recognized.
+    pub fn arch_of(data: &[u8]) -> Option<&'static str> {
+        if data.get(0x202..0x206) == Some(b"HdrS") {
+            return Some("x86_64");
+        }
+        if data.get(0x38..0x3c) == Some(b"ARM\x64") {
+            return Some("aarch64");
+        }
+
+        if !data.starts_with(b"\x7fELF") {
+            return None;
+        }
+        let machine = data.get(18..20)?;
+        let machine = match data.get(5)? {
+            1 => u16::from_le_bytes([machine[0], machine[1]]),
+            _ => u16::from_be_bytes([machine[0], machine[1]]),
+        };
+
+        match machine {
+            ELF_MACHINE_X86_64 => Some("x86_64"),
+            ELF_MACHINE_AARCH64 => Some("aarch64"),
+            _ => Some("unknown"),
+        }
+    }
+
+    pub struct CpioEntry<'a> {
+        /// Offset of the entry header within the archive.
+        pub offset: usize,
+        pub name: String,
+        pub size: u32,
+        pub data: &'a [u8],
+    }
+
+    /// List the entries of a newc CPIO archive, excluding the trailer.
+    pub fn cpio_entries(archive: &[u8]) -> Result<Vec<CpioEntry<'_>>> {
+        let mut entries = Vec::new();
+        let mut rest = archive;
+
+        loop {
+            let offset = archive.len() - rest.len();
+            let reader = Reader::new(rest)
+                .with_context(|| format!("unable to read CPIO entry at offset {offset}"))?;
+            let entry = reader.entry();
+            if entry.is_trailer() {
+                break;
+            }
+
+            // The newc header is 110 bytes, followed by the NUL-terminated name padded to a
+            // multiple of four bytes.
+            let start = (110 + entry.name().len() + 1).next_multiple_of(4);
+            let data = rest
+                .get(start..start + entry.file_size() as usize)
+                .with_context(|| format!("truncated CPIO entry at offset {offset}"))?;
+
+            entries.push(CpioEntry {
+                offset,
+                name: entry.name().to_string(),
+                size: entry.file_size(),
+                data,
+            });
+
+            rest = reader
+                .finish()
+                .with_context(|| format!("unable to read CPIO entry at offset {offset}"))?;
+        }
+
+        Ok(entries)
     }
 }